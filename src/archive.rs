@@ -0,0 +1,165 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+
+use crate::repository::Repository;
+use crate::errors::SipError;
+use crate::fetcher::{content_hash, SipPost, SipPostType};
+use crate::flood::BatchAction;
+
+// svi postovi koji su ikada procitani, po kljucu posta
+// stara baza ih je cuvala pod ovim kljucem, sada su u tabeli post_archive
+pub const ARCHIVE_KEY: &str = "sip_archive";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    // post je procitan pre uvodjenja arhive
+    Unknown,
+    // prvo citanje, post nije slat
+    Seeded,
+    // zadrzan zbog prevelikog broja novih postova
+    Held,
+    // vlasnik je odbacio zadrzane poruke
+    Discarded,
+    Queued,
+    Delivered,
+    // nijedan webhook nije primio poruku posle svih pokusaja
+    Failed
+}
+
+// verzija naslova i sadrzaja posta
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PostRevision {
+    // unix vreme kada je verzija prvi put procitana
+    pub seen_at: i64,
+    pub title: String,
+    pub content: String,
+    pub hash: String
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ArchivedPost {
+    pub source_id: String,
+    // poslednja procitana verzija
+    pub post: SipPost,
+    // kolone u kojima se post pojavljivao
+    pub post_types: Vec<SipPostType>,
+    // unix vremena
+    pub first_seen_at: i64,
+    pub last_seen_at: i64,
+    pub withdrawn_at: Option<i64>,
+    pub revisions: Vec<PostRevision>,
+    pub delivery_status: DeliveryStatus,
+    // webhook-ovi koji su primili poruku o postu
+    pub delivered_to: Vec<u64>
+}
+
+fn status_for_action(action: BatchAction) -> DeliveryStatus {
+    return match action {
+        BatchAction::Deliver => DeliveryStatus::Queued,
+        BatchAction::Seed => DeliveryStatus::Seeded,
+        BatchAction::Hold => DeliveryStatus::Held
+    };
+}
+
+// samo postovi sa datim kljucevima, ostatak arhive se ne cita
+fn read_archived_posts<'a>(database: &dyn Repository, keys: impl Iterator<Item = &'a String>) -> Result<HashMap<String, ArchivedPost>, SipError> {
+    let mut archive: HashMap<String, ArchivedPost> = HashMap::<String, ArchivedPost>::new();
+    for key in keys {
+        if let Some(archived) = database.archived_post(key)? {
+            archive.insert(key.clone(), archived);
+        }
+    }
+    return Ok(archive);
+}
+
+// upisuje procitane postove u arhivu
+// `announced_keys` su postovi za koje se salje nova poruka (novi i premesteni)
+// izmene menjaju vec poslate poruke, pa ne menjaju status isporuke
+pub fn record_posts(database: &mut dyn Repository, source_id: &str, posts: &[SipPost], announced_keys: &HashSet<String>, withdrawn_keys: &HashSet<String>, action: BatchAction) -> Result<(), SipError> {
+    let mut archive: HashMap<String, ArchivedPost> = read_archived_posts(database, posts.iter().map(|post| &post.key).chain(withdrawn_keys.iter()))?;
+    let now: i64 = Utc::now().timestamp();
+
+    for post in posts {
+        let hash: String = content_hash(post);
+        let revision: PostRevision = PostRevision { seen_at: now, title: post.title.clone(), content: post.content.clone(), hash: hash.clone() };
+        let announced: bool = announced_keys.contains(&post.key);
+
+        let archived: &mut ArchivedPost = archive.entry(post.key.clone()).or_insert_with(|| ArchivedPost {
+            source_id: source_id.to_string(),
+            post: post.clone(),
+            post_types: Vec::<SipPostType>::new(),
+            first_seen_at: now,
+            last_seen_at: now,
+            withdrawn_at: None,
+            revisions: Vec::<PostRevision>::new(),
+            delivery_status: if announced { status_for_action(action) } else { DeliveryStatus::Unknown },
+            delivered_to: Vec::<u64>::new()
+        });
+
+        archived.post = post.clone();
+        archived.last_seen_at = now;
+        archived.withdrawn_at = None;
+        if !archived.post_types.contains(&post.post_type) {
+            archived.post_types.push(post.post_type);
+        }
+        if archived.revisions.last().is_none_or(|last| last.hash != hash) {
+            archived.revisions.push(revision);
+        }
+        if announced {
+            archived.delivery_status = status_for_action(action);
+        }
+    }
+
+    for key in withdrawn_keys {
+        if let Some(archived) = archive.get_mut(key) {
+            archived.withdrawn_at = Some(now);
+        }
+    }
+
+    database.save_archived_posts(&archive)?;
+    return Ok(());
+}
+
+fn update_posts<F: Fn(&mut ArchivedPost)>(database: &mut dyn Repository, keys: &[String], update: F) -> Result<(), SipError> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    let mut archive: HashMap<String, ArchivedPost> = read_archived_posts(database, keys.iter())?;
+    let mut changed: bool = false;
+    for key in keys {
+        if let Some(archived) = archive.get_mut(key) {
+            update(archived);
+            changed = true;
+        }
+    }
+
+    if changed {
+        database.save_archived_posts(&archive)?;
+    }
+    return Ok(());
+}
+
+pub fn mark_delivered(database: &mut dyn Repository, keys: &[String], webhook_id: u64) -> Result<(), SipError> {
+    return update_posts(database, keys, |archived| {
+        archived.delivery_status = DeliveryStatus::Delivered;
+        if !archived.delivered_to.contains(&webhook_id) {
+            archived.delivered_to.push(webhook_id);
+        }
+    });
+}
+
+// post koji je stigao bar do jednog webhook-a ostaje isporucen
+pub fn mark_failed(database: &mut dyn Repository, keys: &[String]) -> Result<(), SipError> {
+    return update_posts(database, keys, |archived| {
+        if archived.delivered_to.is_empty() {
+            archived.delivery_status = DeliveryStatus::Failed;
+        }
+    });
+}
+
+pub fn set_status(database: &mut dyn Repository, keys: &[String], status: DeliveryStatus) -> Result<(), SipError> {
+    return update_posts(database, keys, |archived| archived.delivery_status = status);
+}
@@ -3,47 +3,50 @@ use serenity::framework::standard::{Args, CommandResult};
 use serenity::futures::TryFutureExt;
 use serenity::model::prelude::*;
 use serenity::prelude::Context;
+use serenity::utils::parse_role;
 
-use crate::DATABASE;
-use crate::errors::{DiscordError, SipError};
+
+use chrono::{DateTime, Duration, FixedOffset};
+
+use crate::dates::{discord_timestamp, parse_sip_date, start_of_next_day};
+use crate::errors::DiscordError;
+use crate::delivery::DELIVERY_NOTIFY;
+use crate::fetcher::{sort_chronologically, truncate_text, SipPost, EMBED_DESCRIPTION_LIMIT};
+use crate::flood::{approve_batch, discard_batch, HeldBatch};
 use crate::logger::current_date_time;
+use crate::repository::{self, SharedRepository};
+use crate::sources::all_sources;
+use crate::webhooks::{is_sipbot_webhook, remove_webhook, WEBHOOK_NAME};
+
+// SipBot webhook u kanalu, ako postoji
+async fn find_channel_webhook(context: &Context, channel_id: ChannelId) -> Result<Option<Webhook>, DiscordError> {
+    let channel_webhooks: Vec<Webhook> = channel_id.webhooks(&context.http).map_err(|err| DiscordError::DiscordWebhookError(err.to_string())).await?;
+    let bot_id = context.cache.current_user_id();
+
+    return Ok(channel_webhooks.into_iter().find(|webhook| is_sipbot_webhook(webhook, bot_id)));
+}
 
 #[command]
 #[owners_only]
 #[aliases("sip")]
 pub async fn sip(context: &Context, message: &Message, _: Args) -> CommandResult {
 
-    let channel_webhooks: Vec<Webhook> = message.channel_id.webhooks(&context.http).map_err(|err| DiscordError::DiscordWebhookError(err.to_string())).await?;
-    let bot_id = context.cache.current_user_id();
-
-    let mut current_webhook: Option<Webhook> = None;
-
-    for webhook in channel_webhooks {
-
-        match (&webhook.name, &webhook.user) {
-            (Some(name), Some(user)) => {
-                if name == "SipBot Webhook" && user.id == bot_id {
-                    current_webhook = Some((&webhook).clone());
-                    break;
-                }
-            },
-            _ => {}
-        }
-    };
+    let mut current_webhook: Option<Webhook> = find_channel_webhook(context, message.channel_id).await?;
 
     let current_date_time: (String, String) = current_date_time();
 
     if let Some(existing_webhook) = current_webhook { // webhook postoji, brisemo ga
         let existing_webhook_id: WebhookId = existing_webhook.id;
-        existing_webhook.delete(&context.http).map_err(|err| DiscordError::DiscordWebhookError(err.to_string())).await?;
 
+        // prvo iz baze, da uskladjivanje posle brisanja ne bi prijavilo webhook kao nestao
         {
-            let mut database = DATABASE.lock().await;
-            let mut storage_webhooks: Vec<Webhook> = database.get::<Vec<Webhook>>("sip_hooks").unwrap_or(Vec::<Webhook>::new());
-            storage_webhooks.retain(|storage_webhook| storage_webhook.id != existing_webhook_id);
-            database.set("sip_hooks", &storage_webhooks).map_err(|err| SipError::StorageError(err.to_string()))?;
+            let repository: SharedRepository = repository::from_context(context).await?;
+            let mut database = repository.lock().await;
+            database.atomically(|database| remove_webhook(database, existing_webhook_id.0))?;
         }
 
+        existing_webhook.delete(&context.http).map_err(|err| DiscordError::DiscordWebhookError(err.to_string())).await?;
+
         message.channel_id.send_message(&context.http, |m|
             m
                 .embed(|e|
@@ -74,13 +77,14 @@ pub async fn sip(context: &Context, message: &Message, _: Args) -> CommandResult
         ).map_err(|err| DiscordError::DiscordMessageError(err.to_string())).await?;
 
     } else { // webhook nije nadjen, pravimo ga i dodajemo u bazu
-        current_webhook = Some(message.channel_id.create_webhook_with_avatar(&context.http, "SipBot Webhook", "https://i.imgur.com/dyu12dZ.png").map_err(|err| DiscordError::DiscordWebhookError(err.to_string())).await?);
+        current_webhook = Some(message.channel_id.create_webhook_with_avatar(&context.http, WEBHOOK_NAME, "https://i.imgur.com/dyu12dZ.png").map_err(|err| DiscordError::DiscordWebhookError(err.to_string())).await?);
 
         {
-            let mut database = DATABASE.lock().await;
-            let mut storage_webhooks: Vec<Webhook> = database.get::<Vec<Webhook>>("sip_hooks").unwrap_or(Vec::<Webhook>::new());
-            storage_webhooks.push((&current_webhook.unwrap()).clone());
-            database.set("sip_hooks", &storage_webhooks).map_err(|err| SipError::StorageError(err.to_string()))?;
+            let repository: SharedRepository = repository::from_context(context).await?;
+            let mut database = repository.lock().await;
+            let new_webhook: Webhook = current_webhook.unwrap();
+            // uskladjivanje na webhooks_update dogadjaj ga je mozda vec dodalo
+            database.save_webhook(&new_webhook)?;
         }
 
         message.channel_id.send_message(&context.http, |m|
@@ -115,3 +119,240 @@ pub async fn sip(context: &Context, message: &Message, _: Args) -> CommandResult
 
     return Ok(());
 }
+
+// postovi objavljeni u zadatom periodu, npr. `sipdatum 01.10.2023. 15.10.2023.`
+// bez drugog datuma vraca postove samo za prvi dan
+#[command]
+#[owners_only]
+#[aliases("sipdatum")]
+pub async fn sipdatum(context: &Context, message: &Message, mut args: Args) -> CommandResult {
+
+    let from: Option<DateTime<FixedOffset>> = args.single::<String>().ok().and_then(|raw| parse_sip_date(&raw));
+    let to: Option<DateTime<FixedOffset>> = match args.single::<String>() {
+        Ok(raw) => parse_sip_date(&raw),
+        Err(_) => from
+    };
+
+    let (from, to) = match (from, to) {
+        (Some(from), Some(to)) => match start_of_next_day(&to) {
+            Some(to) => (from, to),
+            None => (from, to + Duration::days(1))
+        },
+        _ => {
+            message.reply(&context.http, "Neispravan datum, primer: `sipdatum 01.10.2023. 15.10.2023.`").map_err(|err| DiscordError::DiscordMessageError(err.to_string())).await?;
+            return Ok(());
+        }
+    };
+
+    let mut posts: Vec<SipPost> = Vec::<SipPost>::new();
+    {
+        let repository: SharedRepository = repository::from_context(context).await?;
+        let database = repository.lock().await;
+        for source in all_sources() {
+            for post_type in source.post_types() {
+                let column_posts: Vec<SipPost> = database.snapshot(&source.snapshot_key(post_type))?;
+                for post in column_posts {
+                    let in_range: bool = post.published_at.is_some_and(|published_at| published_at >= from && published_at < to);
+                    if in_range && !posts.contains(&post) {
+                        posts.push(post);
+                    }
+                }
+            }
+        }
+
+        // postovi koji su skliznuli sa naslovne strane
+        for archived in database.archived_posts_published(from.timestamp(), to.timestamp())? {
+            if !posts.contains(&archived.post) {
+                posts.push(archived.post);
+            }
+        }
+    }
+    sort_chronologically(&mut posts);
+
+    // opis embeda je ogranicen na 4096 karaktera
+    let mut description: String = String::new();
+    for post in posts.iter() {
+        let line: String = format!("{} [{}]({})\n", post.published_at.as_ref().map(discord_timestamp).unwrap_or_default(), post.title, post.link);
+        if description.chars().count() + line.chars().count() > 4096 {
+            break;
+        }
+        description.push_str(&line);
+    }
+    if description.is_empty() {
+        description = "Nema postova u zadatom periodu".to_string();
+    }
+
+    message.channel_id.send_message(&context.http, |m|
+        m
+            .embed(|e|
+                e
+                    .author(|a| a.name("SIP").url("https://sip.elfak.ni.ac.rs/"))
+                    .thumbnail("https://i.imgur.com/dyu12dZ.png")
+                    .title(format!("Postovi ({})", posts.len()))
+                    .description(description)
+                    .color(0x65BD36)
+            )
+            .reference_message(message)
+    ).map_err(|err| DiscordError::DiscordMessageError(err.to_string())).await?;
+
+    return Ok(());
+}
+
+// uloga koja se pominje kada post predje u vazna obavestenja
+// `sipuloga @uloga` postavlja, `sipuloga` bez argumenta uklanja ulogu
+#[command]
+#[owners_only]
+#[aliases("sipuloga")]
+pub async fn sipuloga(context: &Context, message: &Message, mut args: Args) -> CommandResult {
+
+    let webhook: Webhook = match find_channel_webhook(context, message.channel_id).await? {
+        Some(webhook) => webhook,
+        None => {
+            message.reply(&context.http, "U kanalu nije registrovan SipBot webhook").map_err(|err| DiscordError::DiscordMessageError(err.to_string())).await?;
+            return Ok(());
+        }
+    };
+
+    // bez argumenta se uloga uklanja, neispravan argument ne menja podesavanje
+    let role_id: Option<u64> = match args.single::<String>() {
+        Ok(raw) => match parse_role(&raw).or_else(|| raw.parse::<u64>().ok()) {
+            Some(role_id) => Some(role_id),
+            None => {
+                message.reply(&context.http, "Neispravna uloga, primer: `sipuloga @uloga` ili `sipuloga <id uloge>`").map_err(|err| DiscordError::DiscordMessageError(err.to_string())).await?;
+                return Ok(());
+            }
+        },
+        Err(_) => None
+    };
+
+    {
+        let repository: SharedRepository = repository::from_context(context).await?;
+        let mut database = repository.lock().await;
+        database.set_ping_role(webhook.id.0, role_id)?;
+    }
+
+    let reply: String = match role_id {
+        Some(role_id) => format!("Uloga <@&{}> ce biti pomenuta kada post predje u vazna obavestenja", role_id),
+        None => "Uloga za vazna obavestenja je uklonjena".to_string()
+    };
+    // pominjanje u embedu ne obavestava clanove uloge
+    message.channel_id.send_message(&context.http, |m|
+        m
+            .embed(|e|
+                e
+                    .author(|a| a.name("SIP").url("https://sip.elfak.ni.ac.rs/"))
+                    .thumbnail("https://i.imgur.com/dyu12dZ.png")
+                    .description(reply)
+                    .color(0x65BD36)
+            )
+            .reference_message(message)
+    ).map_err(|err| DiscordError::DiscordMessageError(err.to_string())).await?;
+
+    return Ok(());
+}
+
+// spisak zadrzanih poruka, kada komanda nema argument
+// dugacak spisak se skracuje da bi stao u embed
+fn held_batches_description(batches: &[HeldBatch]) -> String {
+    if batches.is_empty() {
+        return "Nema zadrzanih poruka".to_string();
+    }
+    let description: String = batches
+        .iter()
+        .map(|batch| format!("`{}` - {}, {} postova, <t:{}:f>", batch.id, batch.source_id, batch.post_count, batch.held_at))
+        .collect::<Vec<String>>()
+        .join("\n");
+    return truncate_text(&description, EMBED_DESCRIPTION_LIMIT);
+}
+
+async fn reply_with_embed(context: &Context, message: &Message, title: &str, description: String) -> Result<(), DiscordError> {
+    message.channel_id.send_message(&context.http, |m|
+        m
+            .embed(|e|
+                e
+                    .author(|a| a.name("SIP").url("https://sip.elfak.ni.ac.rs/"))
+                    .thumbnail("https://i.imgur.com/dyu12dZ.png")
+                    .title(title)
+                    .description(description)
+                    .color(0x65BD36)
+            )
+            .reference_message(message)
+    ).map_err(|err| DiscordError::DiscordMessageError(err.to_string())).await?;
+
+    return Ok(());
+}
+
+// salje poruke zadrzane zbog prevelikog broja novih postova
+// `sipodobri <id>`, bez argumenta prikazuje zadrzane poruke
+#[command]
+#[owners_only]
+#[aliases("sipodobri")]
+pub async fn sipodobri(context: &Context, message: &Message, mut args: Args) -> CommandResult {
+
+    let id: Option<u64> = args.single::<u64>().ok();
+
+    let description: String = {
+        let repository: SharedRepository = repository::from_context(context).await?;
+        let mut database = repository.lock().await;
+        match id {
+            Some(id) => match database.atomically(|database| approve_batch(database, id))? {
+                Some(enqueued) => format!("Zadrzane poruke `{}` su odobrene, upisano {} poruka", id, enqueued),
+                None => format!("Ne postoje zadrzane poruke `{}`", id)
+            },
+            None => held_batches_description(&database.held_batches()?)
+        }
+    };
+
+    if id.is_some() {
+        DELIVERY_NOTIFY.notify_one();
+    }
+
+    reply_with_embed(context, message, "Zadrzane poruke", description).await?;
+    return Ok(());
+}
+
+// odbacuje zadrzane poruke, stanje izvora ostaje upisano pa se postovi ne salju ponovo
+// `sipodbaci <id>`, bez argumenta prikazuje zadrzane poruke
+#[command]
+#[owners_only]
+#[aliases("sipodbaci")]
+pub async fn sipodbaci(context: &Context, message: &Message, mut args: Args) -> CommandResult {
+
+    let id: Option<u64> = args.single::<u64>().ok();
+
+    let description: String = {
+        let repository: SharedRepository = repository::from_context(context).await?;
+        let mut database = repository.lock().await;
+        match id {
+            Some(id) => match database.atomically(|database| discard_batch(database, id))? {
+                Some(batch) => format!("Zadrzane poruke `{}` ({} postova) su odbacene", id, batch.post_count),
+                None => format!("Ne postoje zadrzane poruke `{}`", id)
+            },
+            None => held_batches_description(&database.held_batches()?)
+        }
+    };
+
+    reply_with_embed(context, message, "Zadrzane poruke", description).await?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::Delivery;
+
+    fn batch(id: u64) -> HeldBatch {
+        return HeldBatch { id, source_id: "sip".to_string(), post_count: 12, held_at: 1700000000, deliveries: Vec::<Delivery>::new() };
+    }
+
+    #[test]
+    fn held_batches_fit_the_embed() {
+        assert_eq!(held_batches_description(&[]), "Nema zadrzanih poruka");
+        assert_eq!(held_batches_description(&[batch(1)]), "`1` - sip, 12 postova, <t:1700000000:f>");
+
+        let batches: Vec<HeldBatch> = (1..=500).map(batch).collect();
+        let description: String = held_batches_description(&batches);
+        assert!(description.chars().count() <= EMBED_DESCRIPTION_LIMIT);
+        assert!(description.starts_with("`1` - sip"));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::errors::SipError;
+
+// podesavanja iz .env fajla
+// sva podesavanja osim TOKEN i PREFIX su opciona, ali postavljena vrednost mora biti ispravna
+
+// discord ne prihvata poruku webhook-a sa fajlovima vecim od 10 MB ukupno
+pub const DISCORD_UPLOAD_LIMIT: u64 = 10 * 1024 * 1024;
+
+pub struct Config {
+    // prefiks komandi
+    pub prefix: String,
+    // otvaranje linka svakog novog posta i preuzimanje celog teksta
+    pub full_content: bool,
+    // linkovi ka fajlovima sa stranice posta se prikazuju kao polja embeda
+    pub attachments: bool,
+    // prilozi se ponovo salju kao fajlovi u poruci
+    pub reupload_attachments: bool,
+    // najveca ukupna velicina fajlova (u bajtovima) koji se ponovo salju u jednoj poruci
+    pub reupload_limit: u64,
+    // vlasnik dobija privatnu poruku kada se nedostupan webhook ukloni
+    pub notify_owner_on_prune: bool,
+    // http klijent za preuzimanje sa izvora (vremena u sekundama)
+    pub http_connect_timeout: u64,
+    pub http_timeout: u64,
+    pub http_user_agent: String,
+    pub http_proxy: Option<String>,
+    pub http_ca_cert: Option<String>,
+    // broj ponavljanja posle prolazne greske i pocetno cekanje u milisekundama
+    pub http_retries: u32,
+    pub http_retry_delay: u64,
+    // raspored citanja, vidi schedule.rs
+    pub poll_schedule: String,
+    pub poll_fast: String,
+    pub poll_normal: String,
+    pub poll_slow: String,
+    pub work_hours: String,
+    pub exam_periods: String,
+    pub exam_margin_days: i64,
+    // najvece slucajno odstupanje u sekundama
+    pub poll_jitter: u64,
+    // json fajl sa css selektorima, vidi selectors.rs
+    pub selectors_file: String,
+    // broj uzastopnih gresaka posle kog se salje upozorenje
+    pub health_failure_threshold: u32,
+    // pad broja postova (u procentima) posle kog se salje upozorenje
+    pub health_drop_percent: usize,
+    // kanal za upozorenja, bez njega se salju vlasniku
+    pub alert_channel: Option<u64>,
+    // najveci broj novih i izmenjenih postova koji se salje bez odobrenja, 0 iskljucuje proveru
+    pub flood_threshold: usize,
+    // sqlite baza sa svim stanjem, vidi database.rs
+    pub database_file: String,
+    // stara pickledb baza koja se jednom prebacuje u sqlite
+    pub legacy_storage_file: String,
+    // broj rezervnih kopija baze, 0 iskljucuje kopije
+    pub backup_count: usize,
+    // najmanji razmak izmedju dve kopije u satima
+    pub backup_interval_hours: u64,
+    // ostecena ili obrisana baza se zamenjuje najnovijom kopijom umesto da se bot zaustavi
+    pub restore_backup: bool
+}
+
+lazy_static::lazy_static! {
+    static ref LOADED_CONFIG: Result<Config, SipError> = Config::from_env();
+    // greska u podesavanjima se prijavljuje iz setup_config, pre prvog koriscenja
+    pub static ref CONFIG: &'static Config = LOADED_CONFIG.as_ref().expect("Invalid configuration");
+}
+
+// cita podesavanja pri pokretanju, neispravna vrednost zaustavlja bota
+pub fn setup_config() -> Result<(), SipError> {
+    return match LOADED_CONFIG.as_ref() {
+        Ok(_) => Ok(()),
+        Err(SipError::ConfigError(why)) => Err(SipError::ConfigError(why.clone())),
+        Err(why) => Err(SipError::ConfigError(why.to_string()))
+    };
+}
+
+// izvor promenljivih, u testovima mapa umesto okruzenja
+type Vars<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+fn env_string(vars: Vars, name: &str) -> Option<String> {
+    return vars(name).map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+}
+
+fn env_flag(vars: Vars, name: &str, default: bool) -> Result<bool, SipError> {
+    let value: String = match env_string(vars, name) {
+        Some(value) => value,
+        None => return Ok(default)
+    };
+    return match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "da" => Ok(true),
+        "0" | "false" | "no" | "ne" => Ok(false),
+        _ => Err(SipError::ConfigError(format!("Invalid {}: {}, expected true or false", name, value)))
+    };
+}
+
+fn env_optional_number<T: std::str::FromStr>(vars: Vars, name: &str) -> Result<Option<T>, SipError> {
+    return match env_string(vars, name) {
+        Some(value) => value.parse::<T>().map(Some).map_err(|_| SipError::ConfigError(format!("Invalid {}: {}, expected a whole number", name, value))),
+        None => Ok(None)
+    };
+}
+
+fn env_number<T: std::str::FromStr>(vars: Vars, name: &str, default: T) -> Result<T, SipError> {
+    return Ok(env_optional_number::<T>(vars, name)?.unwrap_or(default));
+}
+
+impl Config {
+    pub fn from_env() -> Result<Config, SipError> {
+        return Config::from_vars(&|name: &str| std::env::var(name).ok());
+    }
+
+    fn from_vars(vars: Vars) -> Result<Config, SipError> {
+        return Ok(Config {
+            prefix: vars("PREFIX").ok_or_else(|| SipError::ConfigError("Missing PREFIX".to_string()))?,
+            full_content: env_flag(vars, "SIP_FULL_CONTENT", false)?,
+            attachments: env_flag(vars, "SIP_ATTACHMENTS", false)?,
+            reupload_attachments: env_flag(vars, "SIP_REUPLOAD_ATTACHMENTS", false)?,
+            reupload_limit: env_number(vars, "SIP_REUPLOAD_LIMIT", DISCORD_UPLOAD_LIMIT)?.min(DISCORD_UPLOAD_LIMIT),
+            notify_owner_on_prune: env_flag(vars, "SIP_NOTIFY_OWNER_ON_PRUNE", false)?,
+            http_connect_timeout: env_number(vars, "SIP_HTTP_CONNECT_TIMEOUT", 10)?,
+            http_timeout: env_number(vars, "SIP_HTTP_TIMEOUT", 30)?,
+            http_user_agent: env_string(vars, "SIP_HTTP_USER_AGENT").unwrap_or(format!("SipBot/{} (+https://github.com/xTeamStanly/sipbot)", env!("CARGO_PKG_VERSION"))),
+            http_proxy: env_string(vars, "SIP_HTTP_PROXY"),
+            http_ca_cert: env_string(vars, "SIP_HTTP_CA_CERT"),
+            http_retries: env_number(vars, "SIP_HTTP_RETRIES", 3)?,
+            http_retry_delay: env_number(vars, "SIP_HTTP_RETRY_DELAY", 1000)?,
+            poll_schedule: env_string(vars, "SIP_POLL_SCHEDULE").unwrap_or("15m".to_string()),
+            poll_fast: env_string(vars, "SIP_POLL_FAST").unwrap_or("5m".to_string()),
+            poll_normal: env_string(vars, "SIP_POLL_NORMAL").unwrap_or("15m".to_string()),
+            poll_slow: env_string(vars, "SIP_POLL_SLOW").unwrap_or("1h".to_string()),
+            work_hours: env_string(vars, "SIP_WORK_HOURS").unwrap_or("7-20".to_string()),
+            exam_periods: env_string(vars, "SIP_EXAM_PERIODS").unwrap_or_default(),
+            exam_margin_days: env_number(vars, "SIP_EXAM_MARGIN_DAYS", 7)?,
+            poll_jitter: env_number(vars, "SIP_POLL_JITTER", 30)?,
+            selectors_file: env_string(vars, "SIP_SELECTORS_FILE").unwrap_or("./selectors.json".to_string()),
+            health_failure_threshold: env_number(vars, "SIP_HEALTH_FAILURES", 3)?,
+            health_drop_percent: env_number(vars, "SIP_HEALTH_DROP_PERCENT", 50)?,
+            alert_channel: env_optional_number::<u64>(vars, "SIP_ALERT_CHANNEL")?,
+            flood_threshold: env_number(vars, "SIP_FLOOD_THRESHOLD", 10)?,
+            database_file: env_string(vars, "SIP_DATABASE").unwrap_or("./sipbot.db".to_string()),
+            legacy_storage_file: env_string(vars, "SIP_LEGACY_STORAGE").unwrap_or("./storage.json".to_string()),
+            backup_count: env_number(vars, "SIP_BACKUP_COUNT", 5)?,
+            backup_interval_hours: env_number(vars, "SIP_BACKUP_HOURS", 6)?,
+            restore_backup: env_flag(vars, "SIP_RESTORE_BACKUP", false)?
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn config(values: &[(&str, &str)]) -> Result<Config, SipError> {
+        let vars: HashMap<String, String> = values.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        return Config::from_vars(&|name: &str| vars.get(name).cloned());
+    }
+
+    #[test]
+    fn unset_values_use_defaults() {
+        let config: Config = config(&[("PREFIX", "sip"), ("SIP_HTTP_RETRIES", " "), ("SIP_REUPLOAD_LIMIT", "999999999")]).unwrap();
+
+        assert_eq!(config.prefix, "sip");
+        assert_eq!(config.http_retries, 3);
+        assert!(!config.restore_backup);
+        assert_eq!(config.alert_channel, None);
+        assert_eq!(config.reupload_limit, DISCORD_UPLOAD_LIMIT);
+    }
+
+    #[test]
+    fn set_values_are_parsed() {
+        let config: Config = config(&[("PREFIX", "sip"), ("SIP_RESTORE_BACKUP", "Da"), ("SIP_FULL_CONTENT", "0"), ("SIP_BACKUP_COUNT", " 2 "), ("SIP_ALERT_CHANNEL", "123456789012345678")]).unwrap();
+
+        assert!(config.restore_backup);
+        assert!(!config.full_content);
+        assert_eq!(config.backup_count, 2);
+        assert_eq!(config.alert_channel, Some(123456789012345678));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert_eq!(config(&[]).err().unwrap().to_string(), "Config error: Missing PREFIX");
+
+        let invalid: [(&str, &str); 4] = [("SIP_HTTP_RETRIES", "tri"), ("SIP_HEALTH_DROP_PERCENT", "-5"), ("SIP_ALERT_CHANNEL", "#upozorenja"), ("SIP_RESTORE_BACKUP", "mozda")];
+        for (name, value) in invalid {
+            let error: String = config(&[("PREFIX", "sip"), (name, value)]).err().unwrap().to_string();
+            assert!(error.contains(&format!("Invalid {}: {}", name, value)), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,1177 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use chrono::{Local, Utc};
+use pickledb::{PickleDb, SerializationMethod};
+use pickledb::error::ErrorType;
+use rusqlite::{ffi, params, Connection, ErrorCode, OptionalExtension, Savepoint, Transaction};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use serenity::model::webhook::Webhook;
+
+use crate::archive::{ArchivedPost, ARCHIVE_KEY};
+use crate::config::CONFIG;
+use crate::delivery::{DeliveredMessage, Delivery, OutboxEntry, DELIVERED_MESSAGES_KEY, OUTBOX_KEY, PING_ROLES_KEY};
+use crate::errors::SipError;
+use crate::fetcher::SipPost;
+use crate::flood::{HeldBatch, HELD_BATCHES_KEY, HELD_NEXT_ID_KEY};
+use crate::logger;
+use crate::repository::Repository;
+use crate::sources::{PostSource, Validators};
+use crate::webhooks::{PrunedWebhook, PRUNED_WEBHOOKS_KEY};
+
+// sqlite baza sa svim stanjem bota, vidi repository.rs
+// sve sto raste ima svoju tabelu, u `settings` su samo male vrednosti (validatori izvora, brojac zadrzanih poruka)
+
+// sema baze, svaka migracija se izvrsava jednom, redom
+// broj izvrsenih migracija se cuva u `PRAGMA user_version`
+const MIGRATIONS: [&str; 2] = [
+    "
+    CREATE TABLE settings (
+        key TEXT PRIMARY KEY NOT NULL,
+        value TEXT NOT NULL
+    );
+
+    CREATE TABLE subscriptions (
+        webhook_id INTEGER PRIMARY KEY NOT NULL,
+        guild_id INTEGER,
+        channel_id INTEGER,
+        webhook TEXT NOT NULL
+    );
+
+    CREATE TABLE posts (
+        snapshot_key TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        post_key TEXT NOT NULL,
+        post TEXT NOT NULL,
+        PRIMARY KEY (snapshot_key, position)
+    );
+
+    CREATE TABLE post_archive (
+        post_key TEXT PRIMARY KEY NOT NULL,
+        source_id TEXT NOT NULL,
+        first_seen_at INTEGER NOT NULL,
+        last_seen_at INTEGER NOT NULL,
+        withdrawn_at INTEGER,
+        delivery_status TEXT NOT NULL,
+        -- unix vreme objave, NULL ako datum nije prepoznat
+        published_at INTEGER,
+        archived TEXT NOT NULL
+    );
+    CREATE INDEX post_archive_first_seen ON post_archive (first_seen_at);
+    CREATE INDEX post_archive_published ON post_archive (published_at);
+
+    CREATE TABLE deliveries (
+        id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+        webhook_id INTEGER NOT NULL,
+        attempts INTEGER NOT NULL,
+        next_attempt_at INTEGER NOT NULL,
+        delivery TEXT NOT NULL
+    );
+    CREATE INDEX deliveries_next_attempt ON deliveries (next_attempt_at);
+
+    CREATE TABLE delivered_messages (
+        webhook_id INTEGER NOT NULL,
+        message_id INTEGER NOT NULL,
+        embed_index INTEGER NOT NULL,
+        post_key TEXT NOT NULL,
+        PRIMARY KEY (webhook_id, message_id, embed_index)
+    );
+    CREATE INDEX delivered_messages_post ON delivered_messages (post_key, webhook_id);
+    ",
+    // hesevi postova, uloge, zadrzane i uklonjene poruke se vise ne cuvaju kao json u `settings`
+    "
+    CREATE TABLE post_hashes (
+        source_id TEXT NOT NULL,
+        post_key TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        PRIMARY KEY (source_id, post_key)
+    );
+    INSERT OR REPLACE INTO post_hashes (source_id, post_key, hash)
+        SELECT substr(settings.key, 1, length(settings.key) - length('_hashes')), hashes.key, hashes.value
+        FROM settings, json_each(settings.value) AS hashes
+        WHERE settings.key LIKE '%\\_hashes' ESCAPE '\\';
+    DELETE FROM settings WHERE key LIKE '%\\_hashes' ESCAPE '\\';
+
+    CREATE TABLE ping_roles (
+        webhook_id INTEGER PRIMARY KEY NOT NULL,
+        role_id INTEGER NOT NULL
+    );
+    INSERT OR REPLACE INTO ping_roles (webhook_id, role_id)
+        SELECT CAST(roles.key AS INTEGER), roles.value
+        FROM settings, json_each(settings.value) AS roles
+        WHERE settings.key = 'sip_ping_roles';
+    DELETE FROM settings WHERE key = 'sip_ping_roles';
+
+    CREATE TABLE held_batches (
+        id INTEGER PRIMARY KEY NOT NULL,
+        held_at INTEGER NOT NULL,
+        batch TEXT NOT NULL
+    );
+    INSERT OR REPLACE INTO held_batches (id, held_at, batch)
+        SELECT json_extract(batches.value, '$.id'), json_extract(batches.value, '$.held_at'), batches.value
+        FROM settings, json_each(settings.value) AS batches
+        WHERE settings.key = 'sip_held';
+    DELETE FROM settings WHERE key = 'sip_held';
+
+    CREATE TABLE pruned_webhooks (
+        id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+        webhook_id INTEGER NOT NULL,
+        channel_id INTEGER NOT NULL,
+        guild_id INTEGER,
+        reason TEXT NOT NULL,
+        pruned_at INTEGER NOT NULL
+    );
+    INSERT INTO pruned_webhooks (webhook_id, channel_id, guild_id, reason, pruned_at)
+        SELECT json_extract(pruned.value, '$.webhook_id'), json_extract(pruned.value, '$.channel_id'), json_extract(pruned.value, '$.guild_id'),
+            json_extract(pruned.value, '$.reason'), json_extract(pruned.value, '$.pruned_at')
+        FROM settings, json_each(settings.value) AS pruned
+        WHERE settings.key = 'sip_pruned_hooks';
+    DELETE FROM settings WHERE key = 'sip_pruned_hooks';
+    "
+];
+
+// oznaka u `settings` da je stara baza vec prebacena
+const LEGACY_IMPORTED_KEY: &str = "legacy_storage_imported";
+// kljuc pod kojim je stara baza cuvala webhook-ove
+const LEGACY_HOOKS_KEY: &str = "sip_hooks";
+
+// rezervne kopije baze
+const BACKUP_DIR: &str = "./backups";
+
+// gde se cuvaju rezervne kopije, koliko ih ima i da li se kopija vraca kad je baza ostecena
+#[derive(Debug, Clone)]
+pub struct BackupOptions {
+    pub directory: PathBuf,
+    // SIP_BACKUP_COUNT, 0 iskljucuje kopije
+    pub count: usize,
+    // SIP_BACKUP_HOURS
+    pub interval_hours: u64,
+    // SIP_RESTORE_BACKUP
+    pub restore: bool
+}
+
+impl BackupOptions {
+    pub fn from_config() -> BackupOptions {
+        return BackupOptions {
+            directory: PathBuf::from(BACKUP_DIR),
+            count: CONFIG.backup_count,
+            interval_hours: CONFIG.backup_interval_hours,
+            restore: CONFIG.restore_backup
+        };
+    }
+}
+
+pub struct Database {
+    connection: Connection,
+    path: String,
+    backup_options: BackupOptions
+}
+
+fn storage_error(err: impl ToString) -> SipError {
+    return SipError::StorageError(err.to_string());
+}
+
+// discord id-evi su u64, sqlite cuva i64
+fn to_sql_id(id: u64) -> i64 {
+    return id as i64;
+}
+
+fn from_json<T: DeserializeOwned>(json: &str) -> Result<T, SipError> {
+    return serde_json::from_str::<T>(json).map_err(storage_error);
+}
+
+fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<String, SipError> {
+    return serde_json::to_string(value).map_err(storage_error);
+}
+
+// nova pretplata ili osvezena postojeca, red ostaje na istom mestu
+fn upsert_webhook(connection: &Connection, webhook: &Webhook) -> Result<(), SipError> {
+    connection.execute(
+        "INSERT INTO subscriptions (webhook_id, guild_id, channel_id, webhook) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (webhook_id) DO UPDATE SET guild_id = excluded.guild_id, channel_id = excluded.channel_id, webhook = excluded.webhook",
+        params![
+            to_sql_id(webhook.id.0),
+            webhook.guild_id.map(|guild_id| to_sql_id(guild_id.0)),
+            webhook.channel_id.map(|channel_id| to_sql_id(channel_id.0)),
+            to_json(webhook)?
+        ]
+    ).map_err(storage_error)?;
+    return Ok(());
+}
+
+fn replace_snapshot(transaction: &Connection, snapshot_key: &str, posts: &[SipPost]) -> Result<(), SipError> {
+    transaction.execute("DELETE FROM posts WHERE snapshot_key = ?1", params![snapshot_key]).map_err(storage_error)?;
+    for (position, post) in posts.iter().enumerate() {
+        transaction.execute(
+            "INSERT INTO posts (snapshot_key, position, post_key, post) VALUES (?1, ?2, ?3, ?4)",
+            params![snapshot_key, position as i64, post.key, to_json(post)?]
+        ).map_err(storage_error)?;
+    }
+    return Ok(());
+}
+
+// poruka iz stare baze, sa svojim id-em
+fn insert_outbox_entry(connection: &Connection, entry: &OutboxEntry) -> Result<(), SipError> {
+    connection.execute(
+        "INSERT INTO deliveries (id, webhook_id, attempts, next_attempt_at, delivery) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![to_sql_id(entry.id), to_sql_id(entry.webhook_id), entry.attempts, entry.next_attempt_at, to_json(&entry.delivery)?]
+    ).map_err(storage_error)?;
+    return Ok(());
+}
+
+fn insert_delivered_messages(connection: &Connection, messages: &[DeliveredMessage]) -> Result<(), SipError> {
+    for delivered in messages {
+        connection.execute(
+            "INSERT OR REPLACE INTO delivered_messages (webhook_id, message_id, embed_index, post_key) VALUES (?1, ?2, ?3, ?4)",
+            params![to_sql_id(delivered.webhook_id), to_sql_id(delivered.message_id), delivered.embed_index as i64, delivered.link]
+        ).map_err(storage_error)?;
+    }
+    return Ok(());
+}
+
+fn upsert_archived_posts<'a>(transaction: &Connection, posts: impl Iterator<Item = (&'a String, &'a ArchivedPost)>) -> Result<(), SipError> {
+    for (key, archived) in posts {
+        transaction.execute(
+            "INSERT OR REPLACE INTO post_archive (post_key, source_id, first_seen_at, last_seen_at, withdrawn_at, delivery_status, published_at, archived) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                key,
+                archived.source_id,
+                archived.first_seen_at,
+                archived.last_seen_at,
+                archived.withdrawn_at,
+                format!("{:?}", archived.delivery_status),
+                archived.post.published_at.map(|published_at| published_at.timestamp()),
+                to_json(archived)?
+            ]
+        ).map_err(storage_error)?;
+    }
+    return Ok(());
+}
+
+fn upsert_post_hashes<'a>(connection: &Connection, source_id: &str, hashes: impl Iterator<Item = (&'a String, &'a String)>) -> Result<(), SipError> {
+    for (post_key, hash) in hashes {
+        connection.execute(
+            "INSERT INTO post_hashes (source_id, post_key, hash) VALUES (?1, ?2, ?3) ON CONFLICT (source_id, post_key) DO UPDATE SET hash = excluded.hash",
+            params![source_id, post_key, hash]
+        ).map_err(storage_error)?;
+    }
+    return Ok(());
+}
+
+fn upsert_ping_role(connection: &Connection, webhook_id: u64, role_id: u64) -> Result<(), SipError> {
+    connection
+        .execute("INSERT OR REPLACE INTO ping_roles (webhook_id, role_id) VALUES (?1, ?2)", params![to_sql_id(webhook_id), to_sql_id(role_id)])
+        .map_err(storage_error)?;
+    return Ok(());
+}
+
+fn upsert_held_batch(connection: &Connection, batch: &HeldBatch) -> Result<(), SipError> {
+    connection
+        .execute("INSERT OR REPLACE INTO held_batches (id, held_at, batch) VALUES (?1, ?2, ?3)", params![to_sql_id(batch.id), batch.held_at, to_json(batch)?])
+        .map_err(storage_error)?;
+    return Ok(());
+}
+
+fn insert_pruned_webhook(connection: &Connection, pruned: &PrunedWebhook) -> Result<(), SipError> {
+    connection.execute(
+        "INSERT INTO pruned_webhooks (webhook_id, channel_id, guild_id, reason, pruned_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![to_sql_id(pruned.webhook_id), to_sql_id(pruned.channel_id), pruned.guild_id.map(to_sql_id), pruned.reason, pruned.pruned_at]
+    ).map_err(storage_error)?;
+    return Ok(());
+}
+
+fn insert_setting(transaction: &Connection, key: &str, json: &str) -> Result<(), SipError> {
+    transaction.execute("INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)", params![key, json]).map_err(storage_error)?;
+    return Ok(());
+}
+
+// stanje fajla pre otvaranja
+enum FileState {
+    Missing,
+    // postoji, ali nema prava citanja ili je disk nedostupan
+    Unreadable(String),
+    Present
+}
+
+fn file_state(path: &str) -> FileState {
+    return match fs::File::open(path) {
+        Ok(_) => FileState::Present,
+        Err(err) if err.kind() == ErrorKind::NotFound => FileState::Missing,
+        Err(err) => FileState::Unreadable(err.to_string())
+    };
+}
+
+fn timestamp() -> String {
+    return Local::now().format("%Y%m%d-%H%M%S").to_string();
+}
+
+// kopija ostecenog fajla ostaje pored originala, npr. storage.json.corrupt-20231001-120000
+// ista ostecena verzija se ne kopira ponovo pri svakom pokusaju pokretanja
+fn preserve_corrupt(path: &str) -> Result<String, SipError> {
+    let contents: Vec<u8> = fs::read(path).map_err(|err| SipError::FileSystemError(format!("Can't read {}: {}", path, err)))?;
+    let directory: &Path = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let prefix: String = format!("{}corrupt-", backup_prefix(path));
+    if let Ok(entries) = fs::read_dir(directory) {
+        for entry in entries.flatten() {
+            let name: String = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with(&prefix) && !name.ends_with("-wal") && fs::read(entry.path()).is_ok_and(|preserved| preserved == contents) {
+                return Ok(entry.path().display().to_string());
+            }
+        }
+    }
+
+    let preserved: String = format!("{}.corrupt-{}", path, timestamp());
+    fs::copy(path, &preserved).map_err(|err| SipError::FileSystemError(format!("Can't preserve {}: {}", path, err)))?;
+    // nezavrseni upisi sqlite baze
+    let wal: String = format!("{}-wal", path);
+    if Path::new(&wal).exists() {
+        fs::copy(&wal, format!("{}-wal", preserved)).map_err(|err| SipError::FileSystemError(format!("Can't preserve {}: {}", wal, err)))?;
+    }
+    return Ok(preserved);
+}
+
+fn backup_prefix(path: &str) -> String {
+    let file_name: String = Path::new(path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or(path.to_string());
+    return format!("{}.", file_name);
+}
+
+// rezervne kopije baze, od najstarije do najnovije
+// ime kopije je ime baze sa vremenom pravljenja, pa je redosled imena i redosled pravljenja
+fn backups(directory: &Path, path: &str) -> Vec<PathBuf> {
+    let prefix: String = backup_prefix(path);
+    let mut backups: Vec<PathBuf> = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|backup| backup.file_name().is_some_and(|name| name.to_string_lossy().starts_with(&prefix)))
+            .collect(),
+        Err(_) => Vec::<PathBuf>::new()
+    };
+    backups.sort();
+    return backups;
+}
+
+fn is_corruption(err: &rusqlite::Error) -> bool {
+    return matches!(err.sqlite_error_code(), Some(ErrorCode::NotADatabase) | Some(ErrorCode::DatabaseCorrupt));
+}
+
+// otvara postojecu ili pravi novu bazu i proverava da nije ostecena
+fn connect(path: &str) -> Result<Connection, rusqlite::Error> {
+    let connection: Connection = Connection::open(path)?;
+    // upis se ne gubi ako bot stane usred upisa
+    connection.pragma_update(None, "journal_mode", "WAL")?;
+    connection.pragma_update(None, "synchronous", "NORMAL")?;
+
+    let check: String = connection.pragma_query_value(None, "quick_check", |row| row.get::<_, String>(0))?;
+    if check != "ok" {
+        return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_CORRUPT), Some(check)));
+    }
+    return Ok(connection);
+}
+
+// bez SIP_RESTORE_BACKUP bot se ne pokrece, da ne bi poceo od prazne baze i poslao sve postove ponovo
+fn restore_backup(path: &str, backup: &Path, problem: &str, restore: bool) -> Result<Connection, SipError> {
+    if !restore {
+        return Err(SipError::StorageError(format!(
+            "{} {}. Refusing to start with an empty database. Newest backup is {}, set SIP_RESTORE_BACKUP=true to restore it",
+            path, problem, backup.display()
+        )));
+    }
+
+    for stale in [format!("{}-wal", path), format!("{}-shm", path)] {
+        if Path::new(&stale).exists() {
+            fs::remove_file(&stale).map_err(|err| SipError::FileSystemError(format!("Can't remove {}: {}", stale, err)))?;
+        }
+    }
+    fs::copy(backup, path).map_err(|err| SipError::FileSystemError(format!("Can't restore {}: {}", backup.display(), err)))?;
+    logger::log_sync("DB", format!("{} {}, restored {}", path, problem, backup.display()));
+    return connect(path).map_err(|err| SipError::StorageError(format!("Backup {} can't be opened: {}", backup.display(), err)));
+}
+
+// baza koju je bot napravio ima bar jednu tabelu i broj migracija
+fn has_schema(connection: &Connection) -> Result<bool, rusqlite::Error> {
+    let version: i64 = connection.pragma_query_value(None, "user_version", |row| row.get::<_, i64>(0))?;
+    let tables: i64 = connection.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0))?;
+    return Ok(version > 0 || tables > 0);
+}
+
+// postojeca baza, ostecena se cuva i zamenjuje najnovijom kopijom
+// sqlite prazan fajl (npr. posle prekinutog kopiranja) otvara kao ispravnu praznu bazu
+// bez ove provere bot bi krenuo bez pretplata, pa se i fajl bez seme smatra ostecenim
+fn open_existing(path: &str, backup_options: &BackupOptions) -> Result<Connection, SipError> {
+    let empty: bool = fs::metadata(path)
+        .map(|metadata| metadata.len() == 0)
+        .map_err(|err| SipError::StorageError(format!("Can't read {}: {}", path, err)))?;
+
+    let why: String = if empty {
+        "empty file".to_string()
+    } else {
+        match connect(path) {
+            Ok(connection) => match has_schema(&connection) {
+                Ok(true) => return Ok(connection),
+                Ok(false) => "no schema".to_string(),
+                Err(why) => return Err(SipError::StorageError(format!("Can't open {}: {}", path, why)))
+            },
+            Err(why) if is_corruption(&why) => why.to_string(),
+            Err(why) => return Err(SipError::StorageError(format!("Can't open {}: {}", path, why)))
+        }
+    };
+
+    let preserved: String = preserve_corrupt(path)?;
+    let problem: String = format!("is corrupt ({}), a copy is saved as {}", why, preserved);
+    return match backups(&backup_options.directory, path).pop() {
+        Some(backup) => restore_backup(path, &backup, &problem, backup_options.restore),
+        None => Err(SipError::StorageError(format!("{} {}. No backup to restore, refusing to start with an empty database", path, problem)))
+    };
+}
+
+impl Database {
+    // otvara bazu i izvrsava migracije koje nisu izvrsene
+    // ostecena baza se cuva i ne zamenjuje praznom
+    pub fn open(path: &str, backup_options: BackupOptions) -> Result<Database, SipError> {
+        let existed: bool;
+        let connection: Connection = match file_state(path) {
+            FileState::Unreadable(why) => return Err(SipError::StorageError(format!("Can't read {}: {}", path, why))),
+            FileState::Missing => {
+                existed = false;
+                // rezervne kopije postoje, pa je baza obrisana ili premestena
+                match backups(&backup_options.directory, path).pop() {
+                    Some(backup) => restore_backup(path, &backup, "is missing", backup_options.restore)?,
+                    None => connect(path).map_err(storage_error)?
+                }
+            },
+            FileState::Present => {
+                existed = true;
+                open_existing(path, &backup_options)?
+            }
+        };
+
+        let mut database: Database = Database { connection, path: path.to_string(), backup_options };
+        // migracije menjaju semu, pa se pre njih pravi kopija
+        // baza bez seme se nikad ne kopira, prazna kopija bi potisnula ispravne
+        let version: usize = database.schema_version()?;
+        if existed && version > 0 && version < MIGRATIONS.len() {
+            database.create_backup()?;
+        }
+        database.migrate()?;
+        return Ok(database);
+    }
+
+    fn schema_version(&self) -> Result<usize, SipError> {
+        let version: i64 = self.connection
+            .pragma_query_value(None, "user_version", |row| row.get::<_, i64>(0))
+            .map_err(storage_error)?;
+        return Ok(version as usize);
+    }
+
+    // kopija cele baze, najstarije kopije preko SIP_BACKUP_COUNT se brisu
+    fn create_backup(&self) -> Result<(), SipError> {
+        if self.backup_options.count == 0 {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.backup_options.directory).map_err(|err| SipError::FileSystemError(err.to_string()))?;
+
+        let backup: PathBuf = self.backup_options.directory.join(format!("{}{}", backup_prefix(&self.path), timestamp()));
+        if backup.exists() {
+            return Ok(());
+        }
+        self.connection
+            .execute("VACUUM INTO ?1", params![backup.to_string_lossy()])
+            .map_err(|err| SipError::StorageError(format!("Can't back up {}: {}", self.path, err)))?;
+
+        let backups: Vec<PathBuf> = backups(&self.backup_options.directory, &self.path);
+        let excess: usize = backups.len().saturating_sub(self.backup_options.count);
+        for old in backups.iter().take(excess) {
+            if let Err(why) = fs::remove_file(old) {
+                logger::log_sync("DB", format!("Can't remove old backup {}: {}", old.display(), why));
+            }
+        }
+        logger::log_sync("DB", format!("Backed up {} to {}", self.path, backup.display()));
+        return Ok(());
+    }
+
+    fn migrate(&mut self) -> Result<(), SipError> {
+        let version: usize = self.schema_version()?;
+        if version > MIGRATIONS.len() {
+            return Err(SipError::StorageError(format!("Database schema version {} is newer than this build ({})", version, MIGRATIONS.len())));
+        }
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction: Transaction = self.connection.transaction().map_err(storage_error)?;
+            transaction.execute_batch(migration).map_err(|err| SipError::StorageError(format!("Migration {} failed: {}", index + 1, err)))?;
+            transaction.pragma_update(None, "user_version", (index + 1) as i64).map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)?;
+        }
+        return Ok(());
+    }
+
+    // jednokratno prebacivanje stare storage.json baze
+    // webhook-ovi, stanje kolona (levi_stari, desni_stari, ...), outbox i arhiva idu u svoje tabele, ostali kljucevi u `settings`
+    // posle uspesnog prebacivanja fajl se preimenuje, pa se ne cita ponovo
+    // neispravan fajl se cuva i bot se ne pokrece, jer bi bez njega svi postovi bili poslati ponovo
+    pub fn import_legacy_storage(&mut self, path: &str, sources: &[Box<dyn PostSource>]) -> Result<bool, SipError> {
+        if self.setting::<Value>(LEGACY_IMPORTED_KEY)?.is_some() {
+            return Ok(false);
+        }
+        match file_state(path) {
+            FileState::Missing => return Ok(false),
+            FileState::Unreadable(why) => return Err(SipError::StorageError(format!("Can't read {}: {}", path, why))),
+            FileState::Present => {}
+        };
+
+        let corrupt = |why: String| -> SipError {
+            return match preserve_corrupt(path) {
+                Ok(preserved) => SipError::StorageError(format!(
+                    "{} is corrupt ({}), a copy is saved as {}. Refusing to start without its data, repair or remove {} first",
+                    path, why, preserved, path
+                )),
+                Err(err) => err
+            };
+        };
+        let legacy: PickleDb = match PickleDb::load_read_only(path, SerializationMethod::Json) {
+            Ok(legacy) => legacy,
+            Err(err) if matches!(err.get_type(), ErrorType::Serialization) => return Err(corrupt(err.to_string())),
+            Err(err) => return Err(SipError::StorageError(format!("Can't read {}: {}", path, err)))
+        };
+        let snapshot_keys: Vec<String> = sources
+            .iter()
+            .flat_map(|source| source.post_types().iter().map(|post_type| source.snapshot_key(post_type)).collect::<Vec<String>>())
+            .collect();
+        // kljuc heseva izvora u staroj bazi, po id-u izvora
+        let hash_keys: HashMap<String, &str> = sources.iter().map(|source| (source.storage_key("hashes"), source.id())).collect();
+
+        let transaction: Transaction = self.connection.transaction().map_err(storage_error)?;
+        for key in legacy.get_all() {
+            let value: Value = match legacy.get::<Value>(&key) {
+                Some(value) => value,
+                None => return Err(corrupt(format!("value of {} is not valid json", key)))
+            };
+            let invalid = |err: serde_json::Error| corrupt(format!("invalid {}: {}", key, err));
+
+            if key == LEGACY_HOOKS_KEY {
+                let webhooks: Vec<Webhook> = serde_json::from_value::<Vec<Webhook>>(value).map_err(invalid)?;
+                for webhook in webhooks.iter() {
+                    upsert_webhook(&transaction, webhook)?;
+                }
+            } else if snapshot_keys.contains(&key) {
+                let posts: Vec<SipPost> = serde_json::from_value::<Vec<SipPost>>(value).map_err(invalid)?;
+                replace_snapshot(&transaction, &key, &posts)?;
+            } else if key == OUTBOX_KEY {
+                let outbox: Vec<OutboxEntry> = serde_json::from_value::<Vec<OutboxEntry>>(value).map_err(invalid)?;
+                for entry in outbox.iter() {
+                    insert_outbox_entry(&transaction, entry)?;
+                }
+            } else if key == DELIVERED_MESSAGES_KEY {
+                let delivered_messages: Vec<DeliveredMessage> = serde_json::from_value::<Vec<DeliveredMessage>>(value).map_err(invalid)?;
+                insert_delivered_messages(&transaction, &delivered_messages)?;
+            } else if key == ARCHIVE_KEY {
+                let archive: HashMap<String, ArchivedPost> = serde_json::from_value::<HashMap<String, ArchivedPost>>(value).map_err(invalid)?;
+                upsert_archived_posts(&transaction, archive.iter())?;
+            } else if let Some(source_id) = hash_keys.get(&key) {
+                let hashes: HashMap<String, String> = serde_json::from_value::<HashMap<String, String>>(value).map_err(invalid)?;
+                upsert_post_hashes(&transaction, source_id, hashes.iter())?;
+            } else if key == PING_ROLES_KEY {
+                let ping_roles: HashMap<u64, u64> = serde_json::from_value::<HashMap<u64, u64>>(value).map_err(invalid)?;
+                for (webhook_id, role_id) in ping_roles {
+                    upsert_ping_role(&transaction, webhook_id, role_id)?;
+                }
+            } else if key == HELD_BATCHES_KEY {
+                let batches: Vec<HeldBatch> = serde_json::from_value::<Vec<HeldBatch>>(value).map_err(invalid)?;
+                for batch in batches.iter() {
+                    upsert_held_batch(&transaction, batch)?;
+                }
+            } else if key == PRUNED_WEBHOOKS_KEY {
+                let pruned_webhooks: Vec<PrunedWebhook> = serde_json::from_value::<Vec<PrunedWebhook>>(value).map_err(invalid)?;
+                for pruned in pruned_webhooks.iter() {
+                    insert_pruned_webhook(&transaction, pruned)?;
+                }
+            } else {
+                insert_setting(&transaction, &key, &value.to_string())?;
+            }
+        }
+        insert_setting(&transaction, LEGACY_IMPORTED_KEY, &to_json(&Utc::now().timestamp())?)?;
+        transaction.commit().map_err(storage_error)?;
+
+        fs::rename(path, format!("{}.imported", path)).map_err(|err| SipError::FileSystemError(err.to_string()))?;
+        return Ok(true);
+    }
+}
+
+impl Database {
+    // vrednost iz `settings`, None ako ne postoji
+    fn setting<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, SipError> {
+        let json: Option<String> = self.connection
+            .query_row("SELECT value FROM settings WHERE key = ?1", params![key], |row| row.get::<_, String>(0))
+            .optional()
+            .map_err(storage_error)?;
+        return json.map(|json| from_json::<T>(&json)).transpose();
+    }
+
+    fn set_setting<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<(), SipError> {
+        return insert_setting(&self.connection, key, &to_json(value)?);
+    }
+}
+
+impl Repository for Database {
+    // pretplate
+
+    fn webhooks(&self) -> Result<Vec<Webhook>, SipError> {
+        let mut statement = self.connection.prepare("SELECT webhook FROM subscriptions ORDER BY rowid").map_err(storage_error)?;
+        let rows: Vec<String> = statement
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(storage_error)?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(storage_error)?;
+        return rows.iter().map(|json| from_json::<Webhook>(json)).collect();
+    }
+
+    fn save_webhook(&mut self, webhook: &Webhook) -> Result<(), SipError> {
+        return upsert_webhook(&self.connection, webhook);
+    }
+
+    fn delete_webhook(&mut self, webhook_id: u64) -> Result<(), SipError> {
+        self.connection.execute("DELETE FROM subscriptions WHERE webhook_id = ?1", params![to_sql_id(webhook_id)]).map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn ping_roles(&self) -> Result<HashMap<u64, u64>, SipError> {
+        let mut statement = self.connection.prepare("SELECT webhook_id, role_id FROM ping_roles").map_err(storage_error)?;
+        let rows: Vec<(i64, i64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(storage_error)?
+            .collect::<Result<Vec<(i64, i64)>, _>>()
+            .map_err(storage_error)?;
+        return Ok(rows.into_iter().map(|(webhook_id, role_id)| (webhook_id as u64, role_id as u64)).collect());
+    }
+
+    fn set_ping_role(&mut self, webhook_id: u64, role_id: Option<u64>) -> Result<(), SipError> {
+        return match role_id {
+            Some(role_id) => upsert_ping_role(&self.connection, webhook_id, role_id),
+            None => {
+                self.connection.execute("DELETE FROM ping_roles WHERE webhook_id = ?1", params![to_sql_id(webhook_id)]).map_err(storage_error)?;
+                Ok(())
+            }
+        };
+    }
+
+    fn add_pruned_webhook(&mut self, pruned: &PrunedWebhook) -> Result<(), SipError> {
+        return insert_pruned_webhook(&self.connection, pruned);
+    }
+
+    // poslednje stanje kolone, prazno ako kolona jos nije procitana
+
+    fn snapshot(&self, snapshot_key: &str) -> Result<Vec<SipPost>, SipError> {
+        let mut statement = self.connection.prepare("SELECT post FROM posts WHERE snapshot_key = ?1 ORDER BY position").map_err(storage_error)?;
+        let rows: Vec<String> = statement
+            .query_map(params![snapshot_key], |row| row.get::<_, String>(0))
+            .map_err(storage_error)?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(storage_error)?;
+        return rows.iter().map(|json| from_json::<SipPost>(json)).collect();
+    }
+
+    fn set_snapshot(&mut self, snapshot_key: &str, posts: &[SipPost]) -> Result<(), SipError> {
+        let transaction: Savepoint = self.connection.savepoint().map_err(storage_error)?;
+        replace_snapshot(&transaction, snapshot_key, posts)?;
+        transaction.commit().map_err(storage_error)?;
+        return Ok(());
+    }
+
+    // stanje izvora, validatori pod kljucevima izvora kao u staroj bazi
+
+    fn post_hashes(&self, source: &dyn PostSource) -> Result<HashMap<String, String>, SipError> {
+        let mut statement = self.connection.prepare("SELECT post_key, hash FROM post_hashes WHERE source_id = ?1").map_err(storage_error)?;
+        let rows: Vec<(String, String)> = statement
+            .query_map(params![source.id()], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(storage_error)?
+            .collect::<Result<Vec<(String, String)>, _>>()
+            .map_err(storage_error)?;
+        return Ok(rows.into_iter().collect());
+    }
+
+    fn save_post_hashes(&mut self, source: &dyn PostSource, hashes: &HashMap<String, String>) -> Result<(), SipError> {
+        let transaction: Savepoint = self.connection.savepoint().map_err(storage_error)?;
+        upsert_post_hashes(&transaction, source.id(), hashes.iter())?;
+        transaction.commit().map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn delete_post_hashes(&mut self, source: &dyn PostSource, post_keys: &[String]) -> Result<(), SipError> {
+        let transaction: Savepoint = self.connection.savepoint().map_err(storage_error)?;
+        for post_key in post_keys {
+            transaction
+                .execute("DELETE FROM post_hashes WHERE source_id = ?1 AND post_key = ?2", params![source.id(), post_key])
+                .map_err(storage_error)?;
+        }
+        transaction.commit().map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn validators(&self, source: &dyn PostSource) -> Result<Validators, SipError> {
+        return Ok(self.setting::<Validators>(&source.storage_key("validators"))?.unwrap_or_default());
+    }
+
+    fn set_validators(&mut self, source: &dyn PostSource, validators: &Validators) -> Result<(), SipError> {
+        return self.set_setting(&source.storage_key("validators"), validators);
+    }
+
+    // poruke koje cekaju slanje
+
+    fn outbox(&self) -> Result<Vec<OutboxEntry>, SipError> {
+        let mut statement = self.connection
+            .prepare("SELECT id, webhook_id, attempts, next_attempt_at, delivery FROM deliveries ORDER BY id")
+            .map_err(storage_error)?;
+        let rows: Vec<(i64, i64, u32, i64, String)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+            .map_err(storage_error)?
+            .collect::<Result<Vec<(i64, i64, u32, i64, String)>, _>>()
+            .map_err(storage_error)?;
+
+        let mut outbox: Vec<OutboxEntry> = Vec::<OutboxEntry>::with_capacity(rows.len());
+        for (id, webhook_id, attempts, next_attempt_at, delivery) in rows {
+            outbox.push(OutboxEntry { id: id as u64, webhook_id: webhook_id as u64, delivery: from_json(&delivery)?, attempts, next_attempt_at });
+        }
+        return Ok(outbox);
+    }
+
+    fn insert_delivery(&mut self, webhook_id: u64, delivery: &Delivery, next_attempt_at: i64) -> Result<u64, SipError> {
+        self.connection.execute(
+            "INSERT INTO deliveries (webhook_id, attempts, next_attempt_at, delivery) VALUES (?1, 0, ?2, ?3)",
+            params![to_sql_id(webhook_id), next_attempt_at, to_json(delivery)?]
+        ).map_err(storage_error)?;
+        return Ok(self.connection.last_insert_rowid() as u64);
+    }
+
+    fn update_delivery(&mut self, id: u64, delivery: &Delivery) -> Result<(), SipError> {
+        self.connection.execute("UPDATE deliveries SET delivery = ?2 WHERE id = ?1", params![to_sql_id(id), to_json(delivery)?]).map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn reschedule_delivery(&mut self, id: u64, attempts: u32, next_attempt_at: i64) -> Result<(), SipError> {
+        self.connection
+            .execute("UPDATE deliveries SET attempts = ?2, next_attempt_at = ?3 WHERE id = ?1", params![to_sql_id(id), attempts, next_attempt_at])
+            .map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn delete_delivery(&mut self, id: u64) -> Result<(), SipError> {
+        self.connection.execute("DELETE FROM deliveries WHERE id = ?1", params![to_sql_id(id)]).map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn delete_webhook_deliveries(&mut self, webhook_id: u64) -> Result<(), SipError> {
+        self.connection.execute("DELETE FROM deliveries WHERE webhook_id = ?1", params![to_sql_id(webhook_id)]).map_err(storage_error)?;
+        return Ok(());
+    }
+
+    // poslate poruke
+
+    fn delivered_messages(&self, webhook_id: u64, post_key: &str) -> Result<Vec<DeliveredMessage>, SipError> {
+        let mut statement = self.connection
+            .prepare("SELECT message_id, embed_index FROM delivered_messages WHERE post_key = ?1 AND webhook_id = ?2 ORDER BY rowid")
+            .map_err(storage_error)?;
+        let rows: Vec<(i64, i64)> = statement
+            .query_map(params![post_key, to_sql_id(webhook_id)], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(storage_error)?
+            .collect::<Result<Vec<(i64, i64)>, _>>()
+            .map_err(storage_error)?;
+        return Ok(rows
+            .into_iter()
+            .map(|(message_id, embed_index)| DeliveredMessage { webhook_id, message_id: message_id as u64, embed_index: embed_index as usize, link: post_key.to_string() })
+            .collect());
+    }
+
+    fn add_delivered_messages(&mut self, messages: &[DeliveredMessage]) -> Result<(), SipError> {
+        let transaction: Savepoint = self.connection.savepoint().map_err(storage_error)?;
+        insert_delivered_messages(&transaction, messages)?;
+        transaction.commit().map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn delete_delivered_messages(&mut self, webhook_id: u64, post_key: &str) -> Result<(), SipError> {
+        self.connection
+            .execute("DELETE FROM delivered_messages WHERE post_key = ?1 AND webhook_id = ?2", params![post_key, to_sql_id(webhook_id)])
+            .map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn delete_post_messages(&mut self, post_keys: &[String]) -> Result<(), SipError> {
+        let transaction: Savepoint = self.connection.savepoint().map_err(storage_error)?;
+        for post_key in post_keys {
+            transaction.execute("DELETE FROM delivered_messages WHERE post_key = ?1", params![post_key]).map_err(storage_error)?;
+        }
+        transaction.commit().map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn delete_webhook_messages(&mut self, webhook_id: u64) -> Result<(), SipError> {
+        self.connection.execute("DELETE FROM delivered_messages WHERE webhook_id = ?1", params![to_sql_id(webhook_id)]).map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn delivered_post_keys(&self) -> Result<Vec<String>, SipError> {
+        let mut statement = self.connection.prepare("SELECT DISTINCT post_key FROM delivered_messages ORDER BY post_key").map_err(storage_error)?;
+        let keys: Vec<String> = statement
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(storage_error)?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(storage_error)?;
+        return Ok(keys);
+    }
+
+    fn rename_delivered_post(&mut self, old_key: &str, new_key: &str) -> Result<(), SipError> {
+        self.connection.execute("UPDATE delivered_messages SET post_key = ?2 WHERE post_key = ?1", params![old_key, new_key]).map_err(storage_error)?;
+        return Ok(());
+    }
+
+    // zadrzane poruke
+
+    fn held_batches(&self) -> Result<Vec<HeldBatch>, SipError> {
+        let mut statement = self.connection.prepare("SELECT batch FROM held_batches ORDER BY id").map_err(storage_error)?;
+        let rows: Vec<String> = statement
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(storage_error)?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(storage_error)?;
+        return rows.iter().map(|json| from_json::<HeldBatch>(json)).collect();
+    }
+
+    fn next_held_batch_id(&mut self) -> Result<u64, SipError> {
+        let id: u64 = self.setting::<u64>(HELD_NEXT_ID_KEY)?.unwrap_or(0);
+        self.set_setting(HELD_NEXT_ID_KEY, &(id + 1))?;
+        return Ok(id);
+    }
+
+    fn save_held_batch(&mut self, batch: &HeldBatch) -> Result<(), SipError> {
+        return upsert_held_batch(&self.connection, batch);
+    }
+
+    fn take_held_batch(&mut self, id: u64) -> Result<Option<HeldBatch>, SipError> {
+        let json: Option<String> = self.connection
+            .query_row("SELECT batch FROM held_batches WHERE id = ?1", params![to_sql_id(id)], |row| row.get::<_, String>(0))
+            .optional()
+            .map_err(storage_error)?;
+        let batch: HeldBatch = match json {
+            Some(json) => from_json::<HeldBatch>(&json)?,
+            None => return Ok(None)
+        };
+        self.connection.execute("DELETE FROM held_batches WHERE id = ?1", params![to_sql_id(id)]).map_err(storage_error)?;
+        return Ok(Some(batch));
+    }
+
+    // arhiva postova
+
+    fn archived_posts(&self) -> Result<HashMap<String, ArchivedPost>, SipError> {
+        let mut statement = self.connection.prepare("SELECT post_key, archived FROM post_archive").map_err(storage_error)?;
+        let rows: Vec<(String, String)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(storage_error)?
+            .collect::<Result<Vec<(String, String)>, _>>()
+            .map_err(storage_error)?;
+
+        let mut archive: HashMap<String, ArchivedPost> = HashMap::<String, ArchivedPost>::with_capacity(rows.len());
+        for (key, json) in rows {
+            archive.insert(key, from_json(&json)?);
+        }
+        return Ok(archive);
+    }
+
+    fn archived_posts_published(&self, from: i64, to: i64) -> Result<Vec<ArchivedPost>, SipError> {
+        let mut statement = self.connection
+            .prepare("SELECT archived FROM post_archive WHERE published_at >= ?1 AND published_at < ?2 ORDER BY published_at")
+            .map_err(storage_error)?;
+        let rows: Vec<String> = statement
+            .query_map(params![from, to], |row| row.get::<_, String>(0))
+            .map_err(storage_error)?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(storage_error)?;
+        return rows.iter().map(|json| from_json::<ArchivedPost>(json)).collect();
+    }
+
+    fn archived_post(&self, key: &str) -> Result<Option<ArchivedPost>, SipError> {
+        let json: Option<String> = self.connection
+            .query_row("SELECT archived FROM post_archive WHERE post_key = ?1", params![key], |row| row.get::<_, String>(0))
+            .optional()
+            .map_err(storage_error)?;
+        return json.map(|json| from_json::<ArchivedPost>(&json)).transpose();
+    }
+
+    fn save_archived_posts(&mut self, posts: &HashMap<String, ArchivedPost>) -> Result<(), SipError> {
+        let transaction: Savepoint = self.connection.savepoint().map_err(storage_error)?;
+        upsert_archived_posts(&transaction, posts.iter())?;
+        transaction.commit().map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn delete_archived_post(&mut self, key: &str) -> Result<(), SipError> {
+        self.connection.execute("DELETE FROM post_archive WHERE post_key = ?1", params![key]).map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn backup(&mut self) -> Result<(), SipError> {
+        let newest: Option<SystemTime> = backups(&self.backup_options.directory, &self.path).pop().and_then(|backup| fs::metadata(backup).and_then(|metadata| metadata.modified()).ok());
+        let interval: Duration = Duration::from_secs(self.backup_options.interval_hours * 60 * 60);
+        if newest.is_some_and(|newest| newest.elapsed().is_ok_and(|elapsed| elapsed < interval)) {
+            return Ok(());
+        }
+        return self.create_backup();
+    }
+
+    // imenovani savepoint, van transakcije je i pocetak transakcije
+    fn begin(&mut self) -> Result<(), SipError> {
+        self.connection.execute_batch("SAVEPOINT repository").map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn commit(&mut self) -> Result<(), SipError> {
+        self.connection.execute_batch("RELEASE repository").map_err(storage_error)?;
+        return Ok(());
+    }
+
+    fn rollback(&mut self) -> Result<(), SipError> {
+        self.connection.execute_batch("ROLLBACK TO repository; RELEASE repository").map_err(storage_error)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pickledb::PickleDbDumpPolicy;
+    use tempfile::TempDir;
+
+    use crate::fetcher::SipPostType;
+    use crate::sources::SipSource;
+    use crate::test_fixtures::{post, webhook};
+
+    fn memory_database() -> Database {
+        let backup_options: BackupOptions = BackupOptions { directory: PathBuf::new(), count: 0, interval_hours: 0, restore: false };
+        return Database { connection: Connection::open_in_memory().unwrap(), path: ":memory:".to_string(), backup_options };
+    }
+
+    fn backup_options(directory: &TempDir, restore: bool) -> BackupOptions {
+        return BackupOptions { directory: directory.path().join("backups"), count: 3, interval_hours: 6, restore };
+    }
+
+    fn database_path(directory: &TempDir) -> String {
+        return directory.path().join("sip.db").display().to_string();
+    }
+
+    // baza sa jednim webhook-om i njena rezervna kopija
+    fn backed_up_database(directory: &TempDir) -> String {
+        let path: String = database_path(directory);
+        let mut database: Database = Database::open(&path, backup_options(directory, false)).unwrap();
+        database.save_webhook(&webhook(1, 3)).unwrap();
+        database.create_backup().unwrap();
+        return path;
+    }
+
+    fn corrupt_copies(directory: &TempDir) -> Vec<Vec<u8>> {
+        return fs::read_dir(directory.path())
+            .unwrap()
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("sip.db.corrupt-"))
+            .map(|entry| fs::read(entry.path()).unwrap())
+            .collect();
+    }
+
+    fn webhook_ids(database: &Database) -> Vec<u64> {
+        return database.webhooks().unwrap().iter().map(|webhook| webhook.id.0).collect();
+    }
+
+    #[test]
+    fn settings_blobs_move_to_tables() {
+        let mut database: Database = memory_database();
+        database.connection.execute_batch(MIGRATIONS[0]).unwrap();
+        database.connection.pragma_update(None, "user_version", 1).unwrap();
+        database.set_setting("sip_hashes", &HashMap::from([("sip.elfak.ni.ac.rs/vesti:1".to_string(), "abc".to_string())])).unwrap();
+        database.set_setting(PING_ROLES_KEY, &HashMap::from([(10u64, 20u64)])).unwrap();
+        database.set_setting(HELD_BATCHES_KEY, &vec![HeldBatch { id: 3, source_id: "sip".to_string(), post_count: 12, held_at: 100, deliveries: Vec::<Delivery>::new() }]).unwrap();
+        database.set_setting(PRUNED_WEBHOOKS_KEY, &vec![PrunedWebhook { webhook_id: 1, channel_id: 2, guild_id: None, reason: "Unknown Webhook".to_string(), pruned_at: 100 }]).unwrap();
+
+        database.migrate().unwrap();
+
+        let hashes: HashMap<String, String> = database.post_hashes(&crate::sources::SipSource).unwrap();
+        assert_eq!(hashes.get("sip.elfak.ni.ac.rs/vesti:1").map(String::as_str), Some("abc"));
+        assert_eq!(database.ping_roles().unwrap(), HashMap::from([(10, 20)]));
+        assert_eq!(database.held_batches().unwrap().iter().map(|batch| (batch.id, batch.post_count)).collect::<Vec<(u64, usize)>>(), vec![(3, 12)]);
+        let pruned: i64 = database.connection.query_row("SELECT COUNT(*) FROM pruned_webhooks WHERE guild_id IS NULL", [], |row| row.get(0)).unwrap();
+        assert_eq!(pruned, 1);
+        let settings: i64 = database.connection.query_row("SELECT COUNT(*) FROM settings", [], |row| row.get(0)).unwrap();
+        assert_eq!(settings, 0);
+    }
+
+    #[test]
+    fn post_hashes_are_upserted_per_row() {
+        let mut database: Database = memory_database();
+        database.migrate().unwrap();
+        let source = crate::sources::SipSource;
+
+        database.save_post_hashes(&source, &HashMap::from([("a".to_string(), "1".to_string()), ("b".to_string(), "2".to_string())])).unwrap();
+        database.save_post_hashes(&source, &HashMap::from([("b".to_string(), "3".to_string())])).unwrap();
+        database.delete_post_hashes(&source, &["a".to_string()]).unwrap();
+
+        assert_eq!(database.post_hashes(&source).unwrap(), HashMap::from([("b".to_string(), "3".to_string())]));
+    }
+
+    #[test]
+    fn held_batch_is_replaced_by_id() {
+        let mut database: Database = memory_database();
+        database.migrate().unwrap();
+        let mut batch: HeldBatch = HeldBatch { id: 1, source_id: "sip".to_string(), post_count: 11, held_at: 100, deliveries: Vec::<Delivery>::new() };
+        database.save_held_batch(&batch).unwrap();
+        batch.post_count = 12;
+        database.save_held_batch(&batch).unwrap();
+
+        assert_eq!(database.held_batches().unwrap().iter().map(|batch| batch.post_count).collect::<Vec<usize>>(), vec![12]);
+        assert_eq!(database.take_held_batch(1).unwrap().map(|batch| batch.post_count), Some(12));
+        assert!(database.take_held_batch(1).unwrap().is_none());
+    }
+
+    #[test]
+    fn garbage_file_is_preserved_and_refused() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let path: String = database_path(&directory);
+        let garbage: Vec<u8> = b"ovo nije sqlite baza".repeat(500);
+        fs::write(&path, &garbage).unwrap();
+
+        let error: String = Database::open(&path, backup_options(&directory, true)).err().unwrap().to_string();
+
+        assert!(error.contains("No backup to restore"), "{}", error);
+        assert_eq!(fs::read(&path).unwrap(), garbage);
+        assert_eq!(corrupt_copies(&directory), vec![garbage.clone()]);
+
+        // ista ostecena verzija se ne kopira ponovo
+        assert!(Database::open(&path, backup_options(&directory, true)).is_err());
+        assert_eq!(corrupt_copies(&directory).len(), 1);
+    }
+
+    #[test]
+    fn garbage_file_is_replaced_by_newest_backup() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let path: String = backed_up_database(&directory);
+        fs::write(&path, b"ovo nije sqlite baza".repeat(500)).unwrap();
+
+        let error: String = Database::open(&path, backup_options(&directory, false)).err().unwrap().to_string();
+        assert!(error.contains("SIP_RESTORE_BACKUP"), "{}", error);
+
+        let database: Database = Database::open(&path, backup_options(&directory, true)).unwrap();
+        assert_eq!(webhook_ids(&database), vec![1]);
+        assert_eq!(corrupt_copies(&directory).len(), 1);
+    }
+
+    #[test]
+    fn empty_file_is_corrupt() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let path: String = backed_up_database(&directory);
+        fs::write(&path, b"").unwrap();
+
+        let error: String = Database::open(&path, backup_options(&directory, false)).err().unwrap().to_string();
+        assert!(error.contains("empty file"), "{}", error);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+
+        let database: Database = Database::open(&path, backup_options(&directory, true)).unwrap();
+        assert_eq!(webhook_ids(&database), vec![1]);
+    }
+
+    #[test]
+    fn database_without_schema_is_corrupt() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let path: String = database_path(&directory);
+        // ispravan sqlite fajl bez tabela i broja migracija
+        Connection::open(&path).unwrap().execute_batch("CREATE TABLE privremena (id INTEGER); DROP TABLE privremena;").unwrap();
+        assert!(fs::metadata(&path).unwrap().len() > 0);
+
+        let error: String = Database::open(&path, backup_options(&directory, true)).err().unwrap().to_string();
+
+        assert!(error.contains("no schema"), "{}", error);
+        // baza bez seme se ne kopira
+        assert!(backups(&backup_options(&directory, true).directory, &path).is_empty());
+    }
+
+    #[test]
+    fn missing_database_is_restored_from_backup() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let path: String = backed_up_database(&directory);
+        fs::remove_file(&path).unwrap();
+
+        let error: String = Database::open(&path, backup_options(&directory, false)).err().unwrap().to_string();
+        assert!(error.contains("is missing"), "{}", error);
+        assert!(!Path::new(&path).exists());
+
+        let database: Database = Database::open(&path, backup_options(&directory, true)).unwrap();
+        assert_eq!(webhook_ids(&database), vec![1]);
+    }
+
+    #[test]
+    fn missing_database_without_backups_starts_empty() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let path: String = database_path(&directory);
+
+        let database: Database = Database::open(&path, backup_options(&directory, false)).unwrap();
+
+        assert_eq!(database.schema_version().unwrap(), MIGRATIONS.len());
+        assert!(database.webhooks().unwrap().is_empty());
+    }
+
+    #[test]
+    fn old_backups_are_rotated() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let options: BackupOptions = backup_options(&directory, false);
+        let path: String = database_path(&directory);
+        let database: Database = Database::open(&path, options.clone()).unwrap();
+        fs::create_dir_all(&options.directory).unwrap();
+        for old in ["20200101-000000", "20200102-000000", "20200103-000000", "20200104-000000"] {
+            fs::write(options.directory.join(format!("sip.db.{}", old)), b"").unwrap();
+        }
+        // kopije druge baze se ne diraju
+        fs::write(options.directory.join("other.db.20200101-000000"), b"").unwrap();
+
+        database.create_backup().unwrap();
+
+        let names: Vec<String> = backups(&options.directory, &path)
+            .iter()
+            .map(|backup| backup.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names.len(), options.count);
+        assert_eq!(&names[..2], ["sip.db.20200103-000000", "sip.db.20200104-000000"]);
+        assert!(options.directory.join("other.db.20200101-000000").exists());
+    }
+
+    #[test]
+    fn legacy_storage_is_imported() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let legacy_path: String = directory.path().join("storage.json").display().to_string();
+        let mut legacy: PickleDb = PickleDb::new(&legacy_path, PickleDbDumpPolicy::AutoDump, SerializationMethod::Json);
+        legacy.set(LEGACY_HOOKS_KEY, &vec![webhook(1, 3)]).unwrap();
+        legacy.set("levi_stari", &vec![post(SipPostType::New, 1, "Prvi")]).unwrap();
+        legacy.set("sip_hashes", &HashMap::from([("sip.elfak.ni.ac.rs/vesti/1".to_string(), "abc".to_string())])).unwrap();
+        legacy.set(PING_ROLES_KEY, &HashMap::from([(1u64, 7u64)])).unwrap();
+        legacy.set("sip_nepoznat_kljuc", &5).unwrap();
+        drop(legacy);
+
+        let mut database: Database = Database::open(&database_path(&directory), backup_options(&directory, false)).unwrap();
+        let sources: Vec<Box<dyn PostSource>> = vec![Box::new(SipSource)];
+
+        assert!(database.import_legacy_storage(&legacy_path, &sources).unwrap());
+
+        assert!(!Path::new(&legacy_path).exists());
+        assert!(Path::new(&format!("{}.imported", legacy_path)).exists());
+        assert_eq!(webhook_ids(&database), vec![1]);
+        assert_eq!(database.snapshot("levi_stari").unwrap().iter().map(|post| post.title.clone()).collect::<Vec<String>>(), vec!["Prvi"]);
+        assert_eq!(database.post_hashes(&SipSource).unwrap().len(), 1);
+        assert_eq!(database.ping_roles().unwrap(), HashMap::from([(1, 7)]));
+        assert_eq!(database.setting::<i64>("sip_nepoznat_kljuc").unwrap(), Some(5));
+
+        // fajl je vec prebacen
+        fs::copy(format!("{}.imported", legacy_path), &legacy_path).unwrap();
+        assert!(!database.import_legacy_storage(&legacy_path, &sources).unwrap());
+        assert!(Path::new(&legacy_path).exists());
+    }
+}
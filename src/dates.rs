@@ -0,0 +1,107 @@
+use chrono::prelude::*;
+
+use crate::logger::TIMEZONE;
+
+// formati datuma koji se javljaju na sip-u, sa i bez vremena
+const DATE_TIME_FORMATS: [&str; 4] = ["%d.%m.%Y. %H:%M", "%d.%m.%Y %H:%M", "%d.%m.%Y. u %H:%M", "%Y-%m-%d %H:%M:%S"];
+const DATE_FORMATS: [&str; 3] = ["%d.%m.%Y.", "%d.%m.%Y", "%Y-%m-%d"];
+
+// pretvara datum iz teksta u vreme u beogradskoj vremenskoj zoni
+// datum bez vremena se tumaci kao ponoc
+pub fn parse_sip_date(raw: &str) -> Option<DateTime<FixedOffset>> {
+    let raw: String = raw.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+    let naive: NaiveDateTime = DATE_TIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&raw, format).ok())
+        .or_else(|| {
+            DATE_FORMATS
+                .iter()
+                .find_map(|format| NaiveDate::parse_from_str(&raw, format).ok())
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })?;
+
+    return local_date_time(naive);
+}
+
+// vreme u beogradskoj vremenskoj zoni
+// pri vracanju sata (oktobar) se uzima prvo od dva ista vremena
+// vreme koje ne postoji zbog pomeranja sata unapred (mart, 02:00-03:00) se pomera za sat
+fn local_date_time(naive: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+    let date_time: DateTime<_> = match naive.and_local_timezone(*TIMEZONE).earliest() {
+        Some(date_time) => date_time,
+        None => (naive + chrono::Duration::hours(1)).and_local_timezone(*TIMEZONE).earliest()?
+    };
+    return Some(date_time.fixed_offset());
+}
+
+// ponoc na pocetku sledeceg dana, dan pri promeni sata ima 23 ili 25 sati
+pub fn start_of_next_day(date_time: &DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>> {
+    let local_date: NaiveDate = date_time.with_timezone(&*TIMEZONE).date_naive();
+    return local_date_time(local_date.succ_opt()?.and_hms_opt(0, 0, 0)?);
+}
+
+// discord prikazuje vreme u vremenskoj zoni citaoca
+pub fn discord_timestamp(date_time: &DateTime<FixedOffset>) -> String {
+    let style: &str = if date_time.time() == NaiveTime::MIN { "D" } else { "f" };
+    return format!("<t:{}:{}>", date_time.timestamp(), style);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offset_hours(date_time: &DateTime<FixedOffset>) -> i32 {
+        return date_time.offset().local_minus_utc() / 3600;
+    }
+
+    #[test]
+    fn sip_formats() {
+        let expected: DateTime<FixedOffset> = DateTime::parse_from_rfc3339("2023-10-15T14:30:00+02:00").unwrap();
+        assert_eq!(parse_sip_date("15.10.2023. 14:30"), Some(expected));
+        assert_eq!(parse_sip_date("15.10.2023 14:30"), Some(expected));
+        assert_eq!(parse_sip_date(" 15.10.2023.  u 14:30 "), Some(expected));
+        assert_eq!(parse_sip_date("2023-10-15 14:30:00"), Some(expected));
+        assert_eq!(parse_sip_date("15.10.2023."), DateTime::parse_from_rfc3339("2023-10-15T00:00:00+02:00").ok());
+        assert_eq!(parse_sip_date("15.13.2023."), None);
+        assert_eq!(parse_sip_date("juce"), None);
+    }
+
+    #[test]
+    fn midnight_on_dst_days() {
+        // prelazak na letnje racunanje, 31.03.2024. u 02:00
+        let spring: DateTime<FixedOffset> = parse_sip_date("31.03.2024.").unwrap();
+        assert_eq!((spring.time(), offset_hours(&spring)), (NaiveTime::MIN, 1));
+        // povratak na zimsko, 27.10.2024. u 03:00, ponoc je jos u letnjem
+        let autumn: DateTime<FixedOffset> = parse_sip_date("27.10.2024.").unwrap();
+        assert_eq!((autumn.time(), offset_hours(&autumn)), (NaiveTime::MIN, 2));
+        assert_eq!(parse_sip_date("28.10.2024.").map(|date_time| offset_hours(&date_time)), Some(1));
+    }
+
+    #[test]
+    fn skipped_and_repeated_hours() {
+        // 02:30 ne postoji 31.03.2024.
+        assert_eq!(parse_sip_date("31.03.2024. 02:30"), DateTime::parse_from_rfc3339("2024-03-31T03:30:00+02:00").ok());
+        // 02:30 postoji dva puta 27.10.2024.
+        assert_eq!(parse_sip_date("27.10.2024. 02:30"), DateTime::parse_from_rfc3339("2024-10-27T02:30:00+02:00").ok());
+    }
+
+    #[test]
+    fn next_day_on_dst_days() {
+        let autumn: DateTime<FixedOffset> = parse_sip_date("27.10.2024.").unwrap();
+        let next: DateTime<FixedOffset> = start_of_next_day(&autumn).unwrap();
+        assert_eq!(next, DateTime::parse_from_rfc3339("2024-10-28T00:00:00+01:00").unwrap());
+        assert_eq!((next - autumn).num_hours(), 25);
+
+        let spring: DateTime<FixedOffset> = parse_sip_date("31.03.2024.").unwrap();
+        assert_eq!((start_of_next_day(&spring).unwrap() - spring).num_hours(), 23);
+    }
+
+    #[test]
+    fn date_only_is_shown_without_time() {
+        let date: DateTime<FixedOffset> = parse_sip_date("15.10.2023.").unwrap();
+        assert_eq!(discord_timestamp(&date), format!("<t:{}:D>", date.timestamp()));
+        let date_time: DateTime<FixedOffset> = parse_sip_date("15.10.2023. 14:30").unwrap();
+        assert_eq!(discord_timestamp(&date_time), format!("<t:{}:f>", date_time.timestamp()));
+    }
+}
@@ -0,0 +1,642 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use chrono::Utc;
+use crate::repository::{Repository, SharedRepository};
+use serde::{Serialize, Deserialize};
+use serde_json::{Value, json};
+use serenity::http::Http;
+use serenity::json::JsonMap;
+use serenity::model::prelude::{AttachmentType, Embed, Message, UserId};
+use serenity::model::webhook::Webhook;
+use tokio::sync::Notify;
+
+use std::time::Duration;
+
+use crate::config::CONFIG;
+use crate::errors::{FetchErrorKind, SipError};
+use crate::http_client;
+use crate::archive;
+use crate::fetcher::{embed_text_length, fit_embed_to_message, truncate_text, SipAttachment, EMBED_TITLE_LIMIT};
+use crate::logger;
+use crate::webhooks::{dead_webhook_reason, is_permanent_error, is_unknown_message, notify_owner_about_pruned, prune_webhook, PrunedWebhook};
+
+// uloge koje se pominju uz vazne poruke, po webhook-u
+pub const PING_ROLES_KEY: &str = "sip_ping_roles";
+// poruke koje cekaju slanje, po webhook-u
+pub const OUTBOX_KEY: &str = "sip_outbox";
+// poslate poruke sa embedima postova
+// stara baza ih je cuvala pod ovim kljucem, sada su u tabeli delivered_messages
+pub const DELIVERED_MESSAGES_KEY: &str = "sip_messages";
+
+// posle toliko neuspelih pokusaja poruka se odbacuje
+const MAX_DELIVERY_ATTEMPTS: u32 = 20;
+// prvi ponovni pokusaj posle 30 sekundi, najduze cekanje 6 sati
+const RETRY_BASE_SECONDS: i64 = 30;
+const RETRY_MAX_SECONDS: i64 = 6 * 60 * 60;
+
+const WITHDRAWN_POST_COLOR: i32 = 0x808080;
+// oznaka naslova povucenog posta
+pub const WITHDRAWN_TITLE_PREFIX: &str = "[ПОВУЧЕНО] ";
+
+lazy_static::lazy_static! {
+    // budi slanje odmah posle upisa novih poruka
+    pub static ref DELIVERY_NOTIFY: Notify = Notify::new();
+}
+
+// jedna poruka webhook-a: embedi i fajlovi koji se salju uz njih
+// uz svaki embed ide kljuc posta (SipPost::key), ako poruku treba kasnije menjati
+// ping oznacava da se uz poruku pominje uloga podesena za webhook
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookMessage {
+    pub embeds: Vec<Value>,
+    pub links: Vec<Option<String>>,
+    pub uploads: Vec<SipAttachment>,
+    pub ping: bool
+}
+
+// izmena embeda u vec poslatoj poruci
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum EmbedEdit {
+    Replace(Value),
+    Withdraw
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum Delivery {
+    Send(WebhookMessage),
+    Edit {
+        link: String,
+        edit: EmbedEdit
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutboxEntry {
+    pub id: u64,
+    pub webhook_id: u64,
+    pub delivery: Delivery,
+    pub attempts: u32,
+    // unix vreme sledeceg pokusaja
+    pub next_attempt_at: i64
+}
+
+// poslata poruka sa embedom posta, cuva se radi kasnijih izmena
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeliveredMessage {
+    pub webhook_id: u64,
+    pub message_id: u64,
+    pub embed_index: usize,
+    // kljuc posta, ime polja je ostalo iz vremena kada su postovi prepoznavani po linku
+    pub link: String
+}
+
+pub fn delivery_links(delivery: &Delivery) -> Vec<String> {
+    return match delivery {
+        Delivery::Send(message) => message.links.iter().flatten().cloned().collect(),
+        Delivery::Edit { link, .. } => vec![link.clone()]
+    };
+}
+
+// upisuje poruke za sve registrovane webhook-ove
+// poruka koja vec ceka slanje za iste postove se ne upisuje ponovo
+pub fn enqueue(database: &mut dyn Repository, deliveries: Vec<Delivery>) -> Result<usize, SipError> {
+    if deliveries.is_empty() {
+        return Ok(0);
+    }
+
+    let webhooks: Vec<Webhook> = database.webhooks()?;
+    let mut outbox: Vec<OutboxEntry> = database.outbox()?;
+    let now: i64 = Utc::now().timestamp();
+    let mut enqueued: usize = 0;
+
+    for webhook in webhooks.iter() {
+        for delivery in deliveries.iter() {
+            let links: Vec<String> = delivery_links(delivery);
+            let already_queued: bool = !links.is_empty() && outbox.iter().any(|entry|
+                entry.webhook_id == webhook.id.0
+                && matches!((&entry.delivery, delivery), (Delivery::Send(_), Delivery::Send(_)))
+                && delivery_links(&entry.delivery) == links
+            );
+            if already_queued {
+                continue;
+            }
+
+            let id: u64 = database.insert_delivery(webhook.id.0, delivery, now)?;
+            outbox.push(OutboxEntry {
+                id,
+                webhook_id: webhook.id.0,
+                delivery: delivery.clone(),
+                attempts: 0,
+                next_attempt_at: now
+            });
+            enqueued += 1;
+        }
+    }
+
+    return Ok(enqueued);
+}
+
+fn retry_delay(attempts: u32) -> i64 {
+    let exponent: u32 = attempts.saturating_sub(1).min(20);
+    return (RETRY_BASE_SECONDS << exponent).min(RETRY_MAX_SECONDS);
+}
+
+async fn download_attachment(attachment: &SipAttachment) -> Result<Vec<u8>, SipError> {
+    let bytes = http_client::with_retries(|| async {
+        let response: reqwest::Response = http_client::send(http_client::client().get(&attachment.url)).await?;
+        return response.bytes().await.map_err(http_client::body_error);
+    }).await?;
+
+    if bytes.len() as u64 > CONFIG.reupload_limit {
+        return Err(SipError::FetchError(FetchErrorKind::Body, format!("Attachment too large: {}", attachment.url)));
+    }
+
+    return Ok(bytes.to_vec());
+}
+
+fn create_webhook_payload(message: &WebhookMessage, ping_role: Option<u64>) -> Value {
+    return match ping_role {
+        Some(role_id) if message.ping => json!({
+            "content": format!("<@&{}>", role_id),
+            "allowed_mentions": {"roles": [role_id.to_string()]},
+            "embeds": message.embeds
+        }),
+        _ => json!({"embeds": message.embeds})
+    };
+}
+
+// embed procitan sa discord-a, bez praznih vrednosti
+fn embed_to_value(embed: &Embed) -> Value {
+    let mut value: Value = serde_json::to_value(embed).unwrap_or_default();
+    if let Some(map) = value.as_object_mut() {
+        map.retain(|_, field| !field.is_null());
+    }
+    return value;
+}
+
+// menja embed `index` poruke sa embedima `embeds`
+fn apply_embed_edit(embeds: &mut [Value], index: usize, edit: &EmbedEdit) {
+    let reserve: usize = WITHDRAWN_TITLE_PREFIX.chars().count();
+    // ostali embedi poruke, sa mestom za oznaku ako jos nisu povuceni
+    let other_embeds_length: usize = embeds
+        .iter()
+        .enumerate()
+        .filter(|(other, _)| *other != index)
+        .map(|(_, embed)| embed_text_length(embed) + if embed["title"].as_str().unwrap_or_default().starts_with(WITHDRAWN_TITLE_PREFIX) { 0 } else { reserve })
+        .sum();
+
+    let embed: &mut Value = match embeds.get_mut(index) {
+        Some(embed) => embed,
+        None => return
+    };
+    match edit {
+        EmbedEdit::Replace(new_embed) => {
+            *embed = new_embed.clone();
+            fit_embed_to_message(embed, other_embeds_length);
+        },
+        EmbedEdit::Withdraw => {
+            let title: String = embed["title"].as_str().unwrap_or_default().to_string();
+            if !title.starts_with(WITHDRAWN_TITLE_PREFIX) {
+                embed["title"] = json!(truncate_text(&format!("{}{}", WITHDRAWN_TITLE_PREFIX, title), EMBED_TITLE_LIMIT));
+            }
+            embed["color"] = json!(WITHDRAWN_POST_COLOR);
+        }
+    };
+}
+
+async fn send_message(http: &Http, webhook_id: u64, webhook_token: &str, message: &WebhookMessage, ping_role: Option<u64>, downloads: &mut HashMap<String, Vec<u8>>) -> Result<Vec<DeliveredMessage>, serenity::Error> {
+    // fajlovi se preuzimaju jednom za sve webhook-ove
+    for upload in message.uploads.iter() {
+        if downloads.contains_key(&upload.url) {
+            continue;
+        }
+        match download_attachment(upload).await {
+            Ok(data) => { downloads.insert(upload.url.clone(), data); },
+            Err(why) => logger::log("ERR", why.to_string()).await
+        };
+    }
+
+    let payload: Value = create_webhook_payload(message, ping_role);
+    let empty_map: JsonMap = JsonMap::new();
+    let payload_map: &JsonMap = payload.as_object().unwrap_or(&empty_map);
+
+    // zbir velicina fajlova jedne poruke ne sme da predje ogranicenje
+    let mut total_size: u64 = 0;
+    let mut files: Vec<AttachmentType> = Vec::<AttachmentType>::new();
+    for upload in message.uploads.iter() {
+        if let Some(data) = downloads.get(&upload.url) {
+            if total_size + data.len() as u64 > CONFIG.reupload_limit {
+                continue;
+            }
+            total_size += data.len() as u64;
+            files.push(AttachmentType::Bytes { data: Cow::Borrowed(data), filename: upload.name.clone() });
+        }
+    }
+
+    let sent_message: Option<Message> = if files.is_empty() {
+        http.execute_webhook(webhook_id, webhook_token, true, payload_map).await?
+    } else {
+        match http.execute_webhook_with_files(webhook_id, webhook_token, true, files, payload_map).await {
+            Ok(sent_message) => sent_message,
+            // discord nije prihvatio fajlove (npr. 413), embedi sa linkovima ka fajlovima se salju bez njih
+            Err(why) if is_permanent_error(&why) && dead_webhook_reason(&why).is_none() => {
+                logger::log("ERR", format!("Webhook {} rejected the attachments, sending without them: {}", webhook_id, why)).await;
+                http.execute_webhook(webhook_id, webhook_token, true, payload_map).await?
+            },
+            Err(why) => return Err(why)
+        }
+    };
+
+    let mut delivered_messages: Vec<DeliveredMessage> = Vec::<DeliveredMessage>::new();
+    if let Some(sent_message) = sent_message {
+        for (embed_index, link) in message.links.iter().enumerate() {
+            if let Some(link) = link {
+                delivered_messages.push(DeliveredMessage {
+                    webhook_id,
+                    message_id: sent_message.id.0,
+                    embed_index,
+                    link: link.clone()
+                });
+            }
+        }
+    }
+
+    return Ok(delivered_messages);
+}
+
+// menja embed posta u svim porukama webhook-a u kojima je poslat
+async fn edit_delivered_messages(http: &Http, webhook_token: &str, delivered_messages: &[DeliveredMessage], edit: &EmbedEdit) -> Result<(), serenity::Error> {
+    for delivered in delivered_messages {
+        // poruka obrisana u kanalu nema sta da se menja
+        let message: Message = match http.get_webhook_message(delivered.webhook_id, webhook_token, delivered.message_id).await {
+            Ok(message) => message,
+            Err(why) if is_unknown_message(&why) => continue,
+            Err(why) => return Err(why)
+        };
+
+        let mut embeds: Vec<Value> = message.embeds.iter().map(embed_to_value).collect();
+        if delivered.embed_index >= embeds.len() {
+            continue;
+        }
+        apply_embed_edit(&mut embeds, delivered.embed_index, edit);
+
+        let payload: Value = json!({"embeds": embeds});
+        if let Some(payload_map) = payload.as_object() {
+            http.edit_webhook_message(delivered.webhook_id, webhook_token, delivered.message_id, payload_map).await?;
+        }
+    }
+
+    return Ok(());
+}
+
+// upisuje ishod slanja jedne poruke
+// `permanent` greska se ne ponavlja, poruka se odmah odbacuje
+fn complete_entry(database: &mut dyn Repository, entry: &OutboxEntry, result: Result<Vec<DeliveredMessage>, String>, permanent: bool) -> Result<(), SipError> {
+    match result {
+        Ok(new_delivered_messages) => {
+            database.delete_delivery(entry.id)?;
+
+            if let Delivery::Send(_) = &entry.delivery {
+                archive::mark_delivered(database, &delivery_links(&entry.delivery), entry.webhook_id)?;
+            }
+
+            // povucen post se vise ne menja
+            if let Delivery::Edit { link, edit: EmbedEdit::Withdraw } = &entry.delivery {
+                database.delete_delivered_messages(entry.webhook_id, link)?;
+            }
+            database.add_delivered_messages(&new_delivered_messages)?;
+        },
+        Err(_) => {
+            let attempts: u32 = if permanent { MAX_DELIVERY_ATTEMPTS } else { entry.attempts + 1 };
+            if attempts < MAX_DELIVERY_ATTEMPTS {
+                database.reschedule_delivery(entry.id, attempts, Utc::now().timestamp() + retry_delay(attempts))?;
+                return Ok(());
+            }
+
+            database.delete_delivery(entry.id)?;
+            if let Delivery::Send(_) = &entry.delivery {
+                archive::mark_failed(database, &delivery_links(&entry.delivery))?;
+            }
+        }
+    };
+
+    return Ok(());
+}
+
+// poruke kojima je doslo vreme, po redosledu upisa
+// poruka koja ceka ponovni pokusaj zadrzava i sve kasnije poruke istog webhook-a
+// inace bi izmena mogla da stigne pre poruke koju menja
+fn due_entries(outbox: &[OutboxEntry], now: i64) -> Vec<&OutboxEntry> {
+    let mut waiting_webhooks: HashSet<u64> = HashSet::<u64>::new();
+    let mut due: Vec<&OutboxEntry> = Vec::<&OutboxEntry>::new();
+    for entry in outbox {
+        if waiting_webhooks.contains(&entry.webhook_id) {
+            continue;
+        }
+        if entry.next_attempt_at > now {
+            waiting_webhooks.insert(entry.webhook_id);
+            continue;
+        }
+        due.push(entry);
+    }
+    return due;
+}
+
+// salje sve poruke kojima je doslo vreme
+// poruke jednog webhook-a idu redom, posle greske ostale cekaju sledeci pokusaj
+pub async fn deliver_pending(http: &Http, owner_id: UserId, repository: &SharedRepository) {
+    let outbox: Vec<OutboxEntry>;
+    let webhooks: Vec<Webhook>;
+    let ping_roles: HashMap<u64, u64>;
+
+    {
+        let mut database = repository.lock().await;
+        // bez citanja nema slanja, pokusava se ponovo na sledecem budjenju
+        let stored: Result<(Vec<Webhook>, Vec<OutboxEntry>), SipError> = database.webhooks().and_then(|webhooks| Ok((webhooks, database.outbox()?)));
+        let mut queued: Vec<OutboxEntry>;
+        (webhooks, queued) = match stored {
+            Ok(stored) => stored,
+            Err(why) => {
+                logger::log("ERR", why.to_string()).await;
+                return;
+            }
+        };
+        ping_roles = match database.ping_roles() {
+            Ok(ping_roles) => ping_roles,
+            Err(why) => {
+                logger::log("ERR", why.to_string()).await;
+                return;
+            }
+        };
+
+        // poruke za obrisane webhook-ove
+        let removed_webhooks: HashSet<u64> = queued
+            .iter()
+            .map(|entry| entry.webhook_id)
+            .filter(|webhook_id| !webhooks.iter().any(|webhook| webhook.id.0 == *webhook_id && webhook.token.is_some()))
+            .collect();
+        for webhook_id in removed_webhooks.iter() {
+            if let Err(why) = database.delete_webhook_deliveries(*webhook_id) {
+                logger::log("ERR", why.to_string()).await;
+            }
+        }
+        queued.retain(|entry| !removed_webhooks.contains(&entry.webhook_id));
+        outbox = queued;
+    }
+
+    // webhook-ovi cije je slanje u ovom prolazu zakazalo, ostale poruke cekaju sledeci pokusaj
+    let mut blocked_webhooks: HashSet<u64> = HashSet::<u64>::new();
+    let mut downloads: HashMap<String, Vec<u8>> = HashMap::<String, Vec<u8>>::new();
+
+    for entry in due_entries(&outbox, Utc::now().timestamp()) {
+        if blocked_webhooks.contains(&entry.webhook_id) {
+            continue;
+        }
+
+        let webhook_token: &String = match webhooks.iter().find(|webhook| webhook.id.0 == entry.webhook_id).and_then(|webhook| webhook.token.as_ref()) {
+            Some(webhook_token) => webhook_token,
+            None => continue
+        };
+
+        let result: Result<Vec<DeliveredMessage>, serenity::Error> = match &entry.delivery {
+            Delivery::Send(message) => send_message(http, entry.webhook_id, webhook_token, message, ping_roles.get(&entry.webhook_id).copied(), &mut downloads).await,
+            Delivery::Edit { link, edit } => {
+                let link_messages: Result<Vec<DeliveredMessage>, SipError> = {
+                    let database = repository.lock().await;
+                    database.delivered_messages(entry.webhook_id, link)
+                };
+                match link_messages {
+                    Ok(link_messages) => edit_delivered_messages(http, webhook_token, &link_messages, edit).await.map(|_| Vec::<DeliveredMessage>::new()),
+                    // poruka ostaje u outbox-u i zadrzava kasnije poruke webhook-a do sledeceg budjenja
+                    Err(why) => {
+                        logger::log("ERR", why.to_string()).await;
+                        blocked_webhooks.insert(entry.webhook_id);
+                        continue;
+                    }
+                }
+            }
+        };
+
+        if let Err(why) = &result {
+            logger::log("ERR", format!("Delivery {} to webhook {} failed (attempt {}): {}", entry.id, entry.webhook_id, entry.attempts + 1, why)).await;
+            blocked_webhooks.insert(entry.webhook_id);
+
+            // obrisan webhook ili kanal, webhook se uklanja iz baze
+            if let Some(reason) = dead_webhook_reason(why) {
+                let pruned: Result<Option<PrunedWebhook>, SipError> = {
+                    let mut database = repository.lock().await;
+                    database.atomically(|database| prune_webhook(database, entry.webhook_id, &reason))
+                };
+                match pruned {
+                    Ok(Some(pruned)) => {
+                        if let Err(why) = notify_owner_about_pruned(http, owner_id, &pruned).await {
+                            logger::log("ERR", why.to_string()).await;
+                        }
+                    },
+                    Ok(None) => {},
+                    Err(why) => logger::log("ERR", why.to_string()).await
+                };
+                continue;
+            }
+        }
+
+        let permanent: bool = result.as_ref().err().is_some_and(is_permanent_error);
+        let mut database = repository.lock().await;
+        if let Err(why) = database.atomically(|database| complete_entry(database, entry, result.map_err(|err| err.to_string()), permanent)) {
+            logger::log("ERR", why.to_string()).await;
+        }
+    }
+}
+
+pub async fn delivery_main(http: Arc<Http>, owner_id: UserId, repository: SharedRepository) {
+    let mut interval = tokio::time::interval(Duration::from_secs(30));
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {},
+            _ = DELIVERY_NOTIFY.notified() => {}
+        };
+
+        deliver_pending(&http, owner_id, &repository).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{record_posts, DeliveryStatus};
+    use crate::fetcher::{SipPost, SipPostType};
+    use crate::flood::BatchAction;
+    use crate::repository::MemoryRepository;
+    use crate::test_fixtures::{post, repository, webhook};
+
+    fn send(posts: &[SipPost]) -> Delivery {
+        return Delivery::Send(WebhookMessage {
+            embeds: posts.iter().map(|post| json!({"title": post.title})).collect(),
+            links: posts.iter().map(|post| Some(post.key.clone())).collect(),
+            uploads: Vec::<SipAttachment>::new(),
+            ping: false
+        });
+    }
+
+    // jedna poruka u outbox-u za post 1, kao posle citanja sa novim postom
+    fn queued_repository() -> (MemoryRepository, OutboxEntry) {
+        let mut repository: MemoryRepository = repository();
+        let posts: Vec<SipPost> = vec![post(SipPostType::New, 1, "Prvi")];
+        let keys: HashSet<String> = posts.iter().map(|post| post.key.clone()).collect();
+        record_posts(&mut repository, "sip", &posts, &keys, &HashSet::<String>::new(), BatchAction::Deliver).unwrap();
+        enqueue(&mut repository, vec![send(&posts)]).unwrap();
+        let entry: OutboxEntry = repository.outbox().unwrap().remove(0);
+        return (repository, entry);
+    }
+
+    fn status(repository: &MemoryRepository) -> DeliveryStatus {
+        return repository.archived_post(&post(SipPostType::New, 1, "Prvi").key).unwrap().unwrap().delivery_status;
+    }
+
+    fn entry(id: u64, webhook_id: u64, next_attempt_at: i64) -> OutboxEntry {
+        return OutboxEntry { id, webhook_id, delivery: send(&[]), attempts: 1, next_attempt_at };
+    }
+
+    #[test]
+    fn retry_delay_grows_and_is_capped() {
+        assert_eq!(retry_delay(0), RETRY_BASE_SECONDS);
+        assert_eq!(retry_delay(1), 30);
+        assert_eq!(retry_delay(2), 60);
+        assert_eq!(retry_delay(5), 480);
+        assert_eq!(retry_delay(11), RETRY_MAX_SECONDS);
+        assert_eq!(retry_delay(MAX_DELIVERY_ATTEMPTS), RETRY_MAX_SECONDS);
+        assert_eq!(retry_delay(u32::MAX), RETRY_MAX_SECONDS);
+    }
+
+    #[test]
+    fn sent_message_is_recorded() {
+        let (mut repository, entry) = queued_repository();
+        let key: String = post(SipPostType::New, 1, "Prvi").key;
+        let delivered: Vec<DeliveredMessage> = vec![DeliveredMessage { webhook_id: 1, message_id: 50, embed_index: 0, link: key.clone() }];
+
+        complete_entry(&mut repository, &entry, Ok(delivered), false).unwrap();
+
+        assert!(repository.outbox().unwrap().is_empty());
+        assert_eq!(repository.delivered_messages(1, &key).unwrap().len(), 1);
+        assert_eq!(status(&repository), DeliveryStatus::Delivered);
+    }
+
+    #[test]
+    fn failed_message_is_retried_later() {
+        let (mut repository, entry) = queued_repository();
+        let before: i64 = Utc::now().timestamp();
+
+        complete_entry(&mut repository, &entry, Err("500 Internal Server Error".to_string()), false).unwrap();
+
+        let rescheduled: OutboxEntry = repository.outbox().unwrap().remove(0);
+        assert_eq!(rescheduled.attempts, 1);
+        assert!(rescheduled.next_attempt_at >= before + retry_delay(1));
+        assert!(rescheduled.next_attempt_at <= Utc::now().timestamp() + retry_delay(1));
+        assert_eq!(status(&repository), DeliveryStatus::Queued);
+    }
+
+    #[test]
+    fn message_is_dropped_after_the_last_attempt() {
+        let (mut repository, mut entry) = queued_repository();
+        entry.attempts = MAX_DELIVERY_ATTEMPTS - 2;
+
+        complete_entry(&mut repository, &entry, Err("timeout".to_string()), false).unwrap();
+        assert_eq!(repository.outbox().unwrap()[0].attempts, MAX_DELIVERY_ATTEMPTS - 1);
+
+        entry.attempts = MAX_DELIVERY_ATTEMPTS - 1;
+        complete_entry(&mut repository, &entry, Err("timeout".to_string()), false).unwrap();
+        assert!(repository.outbox().unwrap().is_empty());
+        assert_eq!(status(&repository), DeliveryStatus::Failed);
+    }
+
+    #[test]
+    fn permanent_error_is_not_retried() {
+        let (mut repository, entry) = queued_repository();
+
+        complete_entry(&mut repository, &entry, Err("400 Invalid Form Body".to_string()), true).unwrap();
+
+        assert!(repository.outbox().unwrap().is_empty());
+        assert_eq!(status(&repository), DeliveryStatus::Failed);
+    }
+
+    #[test]
+    fn post_delivered_elsewhere_stays_delivered() {
+        let (mut repository, entry) = queued_repository();
+        repository.save_webhook(&webhook(2, 3)).unwrap();
+        archive::mark_delivered(&mut repository, &delivery_links(&entry.delivery), 2).unwrap();
+
+        complete_entry(&mut repository, &entry, Err("400 Invalid Form Body".to_string()), true).unwrap();
+
+        assert_eq!(status(&repository), DeliveryStatus::Delivered);
+    }
+
+    #[test]
+    fn withdrawn_post_messages_are_forgotten() {
+        let mut repository: MemoryRepository = repository();
+        let key: String = post(SipPostType::New, 1, "Prvi").key;
+        repository.add_delivered_messages(&[DeliveredMessage { webhook_id: 1, message_id: 50, embed_index: 0, link: key.clone() }]).unwrap();
+        enqueue(&mut repository, vec![Delivery::Edit { link: key.clone(), edit: EmbedEdit::Withdraw }]).unwrap();
+        let entry: OutboxEntry = repository.outbox().unwrap().remove(0);
+
+        complete_entry(&mut repository, &entry, Ok(Vec::<DeliveredMessage>::new()), false).unwrap();
+
+        assert!(repository.delivered_messages(1, &key).unwrap().is_empty());
+    }
+
+    fn long_embed(title: &str, link: &str) -> Value {
+        let mut embed: Value = json!({"title": title, "url": link, "description": format!("{}\n\n[Прочитај више]({})", "a ".repeat(2000), link)});
+        embed["author"] = json!({"name": "Najnovije vesti"});
+        return embed;
+    }
+
+    #[test]
+    fn replacement_fits_the_message() {
+        let mut embeds: Vec<Value> = vec![long_embed("Prvi", "https://sip.elfak.ni.ac.rs/vesti/1"), long_embed("Drugi", "https://sip.elfak.ni.ac.rs/vesti/2")];
+        let first: Value = embeds[0].clone();
+        let mut replacement: Value = long_embed("Drugi (izmenjen)", "https://sip.elfak.ni.ac.rs/vesti/2");
+        replacement["description"] = json!("b ".repeat(2048));
+
+        apply_embed_edit(&mut embeds, 1, &EmbedEdit::Replace(replacement));
+
+        assert_eq!(embeds[0], first);
+        assert_eq!(embeds[1]["title"], "Drugi (izmenjen)");
+        let description: &str = embeds[1]["description"].as_str().unwrap();
+        assert!(description.starts_with("b b"));
+        assert!(description.ends_with("[Прочитај више](https://sip.elfak.ni.ac.rs/vesti/2)"));
+        assert_eq!(description.matches("Прочитај више").count(), 1);
+
+        // i posle povlacenja oba posta poruka ostaje u ogranicenju
+        apply_embed_edit(&mut embeds, 0, &EmbedEdit::Withdraw);
+        apply_embed_edit(&mut embeds, 1, &EmbedEdit::Withdraw);
+        assert!(embeds.iter().map(embed_text_length).sum::<usize>() <= 6000);
+    }
+
+    #[test]
+    fn short_replacement_is_kept() {
+        let mut embeds: Vec<Value> = vec![json!({"title": "Prvi", "description": "Kratak opis"})];
+        let replacement: Value = json!({"title": "Prvi (izmenjen)", "url": "https://sip.elfak.ni.ac.rs/vesti/1", "description": "Nov kratak opis"});
+
+        apply_embed_edit(&mut embeds, 0, &EmbedEdit::Replace(replacement.clone()));
+        assert_eq!(embeds[0], replacement);
+
+        // nepostojeci embed se ne menja
+        apply_embed_edit(&mut embeds, 3, &EmbedEdit::Withdraw);
+        assert_eq!(embeds, vec![replacement]);
+    }
+
+    #[test]
+    fn later_messages_wait_behind_a_retry() {
+        let now: i64 = 1000;
+        // webhook 1 ceka ponovni pokusaj prve poruke, webhook 2 salje sve
+        let outbox: Vec<OutboxEntry> = vec![entry(1, 1, now + 60), entry(2, 2, now), entry(3, 1, now), entry(4, 2, now - 10)];
+
+        let due: Vec<u64> = due_entries(&outbox, now).iter().map(|entry| entry.id).collect();
+        assert_eq!(due, vec![2, 4]);
+
+        let due: Vec<u64> = due_entries(&outbox, now + 60).iter().map(|entry| entry.id).collect();
+        assert_eq!(due, vec![1, 2, 3, 4]);
+    }
+}
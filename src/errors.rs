@@ -1,19 +1,50 @@
+use std::fmt;
+
 use thiserror::Error;
 
+// vrsta greske pri preuzimanju, odredjuje da li se zahtev ponavlja
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchErrorKind {
+    Timeout,
+    Connect,
+    Status(u16),
+    Body,
+    Request
+}
+
+impl FetchErrorKind {
+    pub fn is_retryable(&self) -> bool {
+        return match self {
+            FetchErrorKind::Timeout | FetchErrorKind::Connect | FetchErrorKind::Body => true,
+            FetchErrorKind::Status(status) => *status == 429 || *status >= 500,
+            FetchErrorKind::Request => false
+        };
+    }
+}
+
+impl fmt::Display for FetchErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            FetchErrorKind::Timeout => write!(f, "timeout"),
+            FetchErrorKind::Connect => write!(f, "connect"),
+            FetchErrorKind::Status(status) => write!(f, "status {}", status),
+            FetchErrorKind::Body => write!(f, "body"),
+            FetchErrorKind::Request => write!(f, "request")
+        };
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum SipError {
     #[error("Storage error: {0}")]
     StorageError(String),
 
-    #[error("Fetch error: {0}")]
-    FetchError(String),
+    #[error("Fetch error ({0}): {1}")]
+    FetchError(FetchErrorKind, String),
 
     #[error("Selector error: {0}")]
     SelectorError(String),
 
-    #[error("Text parse error: {0}")]
-    TextParseError(String),
-
     #[error("Post error: {0}")]
     PostError(String),
 
@@ -21,7 +52,10 @@ pub enum SipError {
     PostParseError(String),
 
     #[error("File system error: {0}")]
-    FileSystemError(String)
+    FileSystemError(String),
+
+    #[error("Config error: {0}")]
+    ConfigError(String)
 }
 
 #[derive(Error, Debug)]
@@ -1,262 +1,1024 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use pickledb::PickleDb;
-use reqwest::Response;
-use scraper::{Html, Selector, ElementRef};
+use chrono::{DateTime, FixedOffset};
+use crate::repository::{Repository, SharedRepository};
 use serde::{Serialize, Deserialize};
 use serde_json::{Value, json};
 use serenity::http::Http;
-use serenity::json::JsonMap;
-use serenity::futures::TryFutureExt;
-use serenity::model::prelude::Embed;
-use serenity::model::webhook::Webhook;
+use serenity::model::prelude::{Embed, UserId};
 
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::archive;
+use crate::config::CONFIG;
+use crate::dates::discord_timestamp;
+use crate::delivery::{enqueue, WITHDRAWN_TITLE_PREFIX, Delivery, EmbedEdit, WebhookMessage, DELIVERY_NOTIFY};
 use crate::errors::SipError;
-use crate::DATABASE;
 use crate::logger;
+use crate::flood::{batch_action, hold_batch, BatchAction, HeldBatch};
+use crate::health::{send_alert, HealthMonitor, HTML_DIRECTORY};
+use crate::selectors;
+use crate::schedule::{next_poll_delay, PollSchedule};
+use crate::sources::{FetchOutcome, ParsedPage, PostSource, Validators};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SipPostType {
     New = 0,
     Important = 1
 }
 
+impl SipPostType {
+    pub fn storage_suffix(&self) -> &'static str {
+        return match self {
+            SipPostType::New => "new",
+            SipPostType::Important => "important"
+        };
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        return match self {
+            SipPostType::New => "Најновије вести",
+            SipPostType::Important => "Важна обавештења"
+        };
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SipAttachment {
+    pub name: String,
+    pub url: String,
+    pub size: Option<u64>
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SipPost {
     pub post_type: SipPostType,
+    // datum kako je napisan na sip-u
     pub date: String,
+    // isti datum u beogradskoj vremenskoj zoni, ako je prepoznat
+    #[serde(default)]
+    pub published_at: Option<DateTime<FixedOffset>>,
     pub title: String,
     pub content: String,
-    pub link: String
+    // apsolutni link posta, bez parametara za pracenje
+    pub link: String,
+    // kljuc po kom se post prepoznaje, "host/putanja:id" ili kanonski link, vidi links.rs
+    #[serde(default)]
+    pub key: String,
+    // izvod kao discord markdown (podebljano, linkovi, liste)
+    // content ostaje obican tekst jer se od njega racuna hes
+    #[serde(default)]
+    pub content_markdown: Option<String>,
+    // ceo tekst sa stranice posta kao discord markdown, ako je preuzet
+    #[serde(default)]
+    pub full_content: Option<String>,
+    // fajlovi sa stranice posta
+    #[serde(default)]
+    pub attachments: Vec<SipAttachment>
+}
+
+impl SipPost {
+    // tekst za opis embeda, najpotpuniji koji postoji
+    pub fn description(&self) -> &str {
+        return self.full_content.as_deref().or(self.content_markdown.as_deref()).unwrap_or(&self.content);
+    }
 }
 
 impl PartialEq for SipPost {
     fn eq(&self, other: &Self) -> bool {
-        self.link == other.link
+        self.key == other.key
     }
 }
 
-// setup selectors
-lazy_static::lazy_static! {
-    static ref ALL_POSTS_SELECTOR: Selector = Selector::parse("div.news-box")   .map_err(|err| SipError::SelectorError(err.to_string())).unwrap();
-    static ref POSTS_SELECTOR: Selector     = Selector::parse("ul li")          .map_err(|err| SipError::SelectorError(err.to_string())).unwrap();
+// postovi bez prepoznatog datuma idu na kraj, redosled medju njima se ne menja
+pub fn sort_chronologically(posts: &mut [SipPost]) {
+    posts.sort_by_key(|post| (post.published_at.is_none(), post.published_at));
+}
 
-    static ref DATE_SELECTOR: Selector      = Selector::parse("p")              .map_err(|err| SipError::SelectorError(err.to_string())).unwrap();
-    static ref TITLE_SELECTOR: Selector     = Selector::parse("h4")             .map_err(|err| SipError::SelectorError(err.to_string())).unwrap();
-    static ref CONTENT_SELECTOR: Selector   = Selector::parse("p")              .map_err(|err| SipError::SelectorError(err.to_string())).unwrap();
-    static ref LINK_SELECTOR: Selector      = Selector::parse("a")              .map_err(|err| SipError::SelectorError(err.to_string())).unwrap();
+// izmena vec objavljenog posta
+pub struct PostUpdate {
+    // stara verzija, ako je jos u poslednjem stanju
+    pub before: Option<SipPost>,
+    pub after: SipPost
 }
 
-pub fn parse_element_to_posts(node: ElementRef, post_type: SipPostType) -> Result<Vec<SipPost>, SipError> {
-    let mut posts: Vec<SipPost> = Vec::<SipPost>::new();
+// sve sto treba poslati posle jednog citanja izvora
+pub struct FetchedPosts {
+    pub new_posts: Vec<SipPost>,
+    pub updated_posts: Vec<PostUpdate>,
+    pub withdrawn_posts: Vec<SipPost>,
+    // postovi premesteni iz najnovijih vesti u vazna obavestenja
+    pub promoted_posts: Vec<SipPost>
+}
 
-    for post_element in node.select(&POSTS_SELECTOR) {
+// postovi koji su nestali iz kolone, a ispod kojih su ostali stariji postovi
+// postovi koji su samo skliznuli sa dna liste se ne racunaju kao povuceni
+fn find_withdrawn_posts(old_columns: &[Vec<SipPost>], posts: &[SipPost]) -> Vec<SipPost> {
+    let current_keys: HashSet<&String> = posts.iter().map(|post| &post.key).collect();
+    let mut withdrawn_posts: Vec<SipPost> = Vec::<SipPost>::new();
 
-        let date: String = post_element
-            .select(&DATE_SELECTOR)
-            .nth(0)
-            .ok_or_else(|| SipError::PostParseError("Error parsing date".to_string()))?
-            .text()
-            .map(|t| t.trim().to_string())
-            .collect();
+    for old_column in old_columns {
+        let last_present: Option<usize> = old_column.iter().rposition(|old_post| current_keys.contains(&old_post.key));
+        if let Some(last_present) = last_present {
+            for old_post in old_column[..last_present].iter() {
+                if !current_keys.contains(&old_post.key) && !withdrawn_posts.contains(old_post) {
+                    withdrawn_posts.push(old_post.clone());
+                }
+            }
+        }
+    }
 
-        let title: String = post_element
-            .select(&TITLE_SELECTOR)
-            .nth(0)
-            .ok_or_else(|| SipError::PostParseError("Error parsing title".to_string()))?
-            .text()
-            .map(|t| t.trim().to_string())
-            .collect();
+    return withdrawn_posts;
+}
 
-        let content: String = post_element
-            .select(&CONTENT_SELECTOR)
-            .nth(1)
-            .ok_or_else(|| SipError::PostParseError("Error parsing content".to_string()))?
-            .text()
-            .map(|t| t.trim().to_string())
-            .collect();
+// FNV-1a, stabilan izmedju verzija kompajlera (za razliku od DefaultHasher-a)
+pub fn hash_bytes<I: IntoIterator<Item = u8>>(bytes: I) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return format!("{:016x}", hash);
+}
 
-        let link: String = post_element
-            .select(&LINK_SELECTOR)
-            .nth(0)
-            .ok_or_else(|| SipError::PostParseError("Error parsing link".to_string()))?
-            .value()
-            .attr("href")
-            .ok_or_else(|| SipError::PostParseError("Error parsing hyperlink".to_string()))?
-            .trim()
-            .to_string();
-
-        posts.push(SipPost {
-            post_type: post_type.clone(),
-            title,
-            content,
-            date,
-            link
-        });
+pub fn content_hash(post: &SipPost) -> String {
+    return hash_bytes(post.title.bytes().chain([0u8]).chain(post.content.bytes()));
+}
+
+// poslednje sacuvano stanje izvora
+pub struct SourceState {
+    pub old_columns: Vec<Vec<SipPost>>,
+    // hesevi naslova i sadrzaja po kljucu posta
+    pub hashes: HashMap<String, String>,
+    pub validators: Validators
+}
+
+// razlike izmedju sacuvanog i upravo procitanog stanja
+pub struct PostsDiff {
+    pub fetched: FetchedPosts,
+    // hesevi postova sa stranice, upisuju se preko sacuvanih
+    pub hashes: HashMap<String, String>,
+    // postovi koji su skliznuli sa liste, njihove poruke se vise ne menjaju
+    pub scrolled_off_keys: HashSet<String>
+}
+
+pub fn read_source_state(database: &dyn Repository, source: &dyn PostSource) -> Result<SourceState, SipError> {
+    let mut old_columns: Vec<Vec<SipPost>> = Vec::<Vec<SipPost>>::new();
+    for post_type in source.post_types() {
+        let old_column_posts: Vec<SipPost> = database.snapshot(&source.snapshot_key(post_type))?;
+        old_columns.push(old_column_posts);
     }
 
-    return Ok(posts);
+    let hashes: HashMap<String, String> = database.post_hashes(source)?;
+    let validators: Validators = database.validators(source)?;
+
+    return Ok(SourceState { old_columns, hashes, validators });
 }
 
-// vraca sve sip postove za slanje
-pub async fn fetch_posts(database: &mut PickleDb) -> Result<Vec<SipPost>, SipError> {
+// izdvajanje novih, izmenjenih, povucenih i premestenih postova
+// `partial` oznacava da su neki postovi preskoceni, pa se nestali postovi ne smatraju povucenim
+pub fn diff_posts(state: SourceState, posts: &[SipPost], partial: bool) -> PostsDiff {
+    let old_columns: Vec<Vec<SipPost>> = state.old_columns;
+    let old_hashes: HashMap<String, String> = state.hashes;
+    let mut hashes: HashMap<String, String> = HashMap::<String, String>::new();
 
-    logger::log_sync("SPFCH", "READING POSTS");
-    // linkovi starih postova, posto uklanjamo duplikate preko linkova
-    let old_left_posts_links: HashSet<String> = database
-        .get::<Vec<SipPost>>("levi_stari")
-        .ok_or_else(|| SipError::StorageError("No left posts found".to_string()))?
-        .into_iter()
-        .map(|old_left_post| old_left_post.link)
-        .collect();
+    // stari postovi svih kolona po kljucu, posto uklanjamo duplikate preko kljuceva
+    let mut old_posts: HashMap<String, SipPost> = HashMap::<String, SipPost>::new();
+    for old_post in old_columns.iter().flatten() {
+        old_posts.entry(old_post.key.clone()).or_insert(old_post.clone());
+    }
 
-    let old_right_posts_links: HashSet<String> = database
-        .get::<Vec<SipPost>>("desni_stari")
-        .ok_or_else(|| SipError::StorageError("No right posts found".to_string()))?
-        .into_iter()
-        .map(|old_right_post| old_right_post.link)
-        .collect();
+    // uklanjanje dupliciranih postova
+    // prioritet se daje vaznim obavestenjima
+    let mut final_posts_keys: HashSet<String> = HashSet::<String>::new();
+    let mut final_posts: Vec<SipPost> = Vec::<SipPost>::new();
+    let mut updated_posts: Vec<PostUpdate> = Vec::<PostUpdate>::new();
+    let mut promoted_posts: Vec<SipPost> = Vec::<SipPost>::new();
+
+    let old_keys_of_type = |post_type: SipPostType| -> HashSet<&String> {
+        return old_columns.iter().flatten().filter(|old_post| old_post.post_type == post_type).map(|old_post| &old_post.key).collect();
+    };
+    let old_new_keys: HashSet<&String> = old_keys_of_type(SipPostType::New);
+    let old_important_keys: HashSet<&String> = old_keys_of_type(SipPostType::Important);
+
+    // u okviru kolone hronoloski, od najstarijeg
+    for post_type in [SipPostType::Important, SipPostType::New] {
+        let mut column_posts: Vec<SipPost> = Vec::<SipPost>::new();
+        for post in posts.iter().filter(|post| post.post_type == post_type) {
+            if !final_posts_keys.insert(post.key.clone()) {
+                continue;
+            }
+
+            let new_hash: String = content_hash(post);
+            let old_post: Option<&SipPost> = old_posts.get(&post.key);
+            // postovi iz stanja pre uvodjenja heseva
+            let old_hash: Option<String> = old_hashes.get(&post.key).cloned().or_else(|| old_post.map(content_hash));
+
+            match old_hash {
+                None if old_post.is_none() => column_posts.push(post.clone()),
+                Some(old_hash) if old_hash != new_hash => updated_posts.push(PostUpdate { before: old_post.cloned(), after: post.clone() }),
+                _ => {}
+            };
+
+            if post.post_type == SipPostType::Important && old_new_keys.contains(&post.key) && !old_important_keys.contains(&post.key) {
+                promoted_posts.push(post.clone());
+            }
+
+            hashes.insert(post.key.clone(), new_hash);
+        }
+        sort_chronologically(&mut column_posts);
+        final_posts.extend(column_posts);
+    }
+
+    let (withdrawn_posts, scrolled_off_keys): (Vec<SipPost>, HashSet<String>) = if partial {
+        (Vec::<SipPost>::new(), HashSet::<String>::new())
+    } else {
+        let withdrawn_posts: Vec<SipPost> = find_withdrawn_posts(&old_columns, posts);
+
+        let current_keys: HashSet<&String> = posts.iter().map(|post| &post.key).collect();
+        let withdrawn_keys: HashSet<&String> = withdrawn_posts.iter().map(|post| &post.key).collect();
+        let scrolled_off_keys: HashSet<String> = old_posts
+            .into_keys()
+            .filter(|key| !current_keys.contains(key) && !withdrawn_keys.contains(key))
+            .collect();
+
+        (withdrawn_posts, scrolled_off_keys)
+    };
+
+    return PostsDiff {
+        fetched: FetchedPosts {
+            new_posts: final_posts,
+            updated_posts,
+            withdrawn_posts,
+            promoted_posts
+        },
+        hashes,
+        scrolled_off_keys
+    };
+}
+
+// ishod upisa novog stanja izvora
+pub enum SaveOutcome {
+    // broj poruka upisanih u outbox
+    Enqueued(usize),
+    Seeded,
+    Held(HeldBatch)
+}
+
+// upisuje poruke i novo stanje izvora
+pub fn save_source_state(database: &mut dyn Repository, source: &dyn PostSource, posts: &[SipPost], diff: PostsDiff, validators: &Validators, action: BatchAction, upload_limit: Option<u64>) -> Result<SaveOutcome, SipError> {
+    let PostsDiff { fetched, hashes, scrolled_off_keys } = diff;
+    let post_count: usize = fetched.new_posts.len() + fetched.updated_posts.len();
+    let announced_keys: HashSet<String> = fetched.new_posts.iter().chain(fetched.promoted_posts.iter()).map(|post| post.key.clone()).collect();
+    let withdrawn_keys: HashSet<String> = fetched.withdrawn_posts.iter().map(|post| post.key.clone()).collect();
+    let deliveries: Vec<Delivery> = create_deliveries(fetched, source, upload_limit);
+
+    // poruke se upisuju pre novog stanja
+    // ako program padne izmedju dva upisa, postovi se ponovo otkrivaju umesto da se izgube
+    let outcome: SaveOutcome = match action {
+        BatchAction::Deliver => SaveOutcome::Enqueued(enqueue(database, deliveries)?),
+        BatchAction::Seed => SaveOutcome::Seeded,
+        BatchAction::Hold => SaveOutcome::Held(hold_batch(database, source.id(), post_count, deliveries)?)
+    };
+
+    for post_type in source.post_types() {
+        let column_posts: Vec<SipPost> = posts.iter().filter(|post| post.post_type == *post_type).cloned().collect();
+        database.set_snapshot(&source.snapshot_key(post_type), &column_posts)?;
+    }
+    database.save_post_hashes(source, &hashes)?;
+    archive::record_posts(database, source.id(), posts, &announced_keys, &withdrawn_keys, action)?;
+    database.set_validators(source, validators)?;
+
+    if !scrolled_off_keys.is_empty() {
+        database.delete_post_messages(&scrolled_off_keys.into_iter().collect::<Vec<String>>())?;
+    }
 
+    return Ok(outcome);
+}
+
+// pronalazi promene na izvoru i upisuje poruke za njih, vraca broj upisanih poruka
+// baza je zakljucana samo dok se stanje cita i upisuje, ne i tokom preuzimanja sa mreze
+pub async fn fetch_posts(source: &dyn PostSource, health: &mut HealthMonitor, repository: &SharedRepository) -> Result<usize, SipError> {
+
+    logger::log_sync("SPFCH", format!("READING POSTS [{}]", source.id()));
+    let state: SourceState = {
+        let database = repository.lock().await;
+        read_source_state(&**database, source)?
+    };
     logger::log_sync("SPFCH", "POSTS READ");
 
-    // pribavljanje sa sip-a
-    let response: Response = reqwest::get("https://sip.elfak.ni.ac.rs/").map_err(|err| SipError::FetchError(err.to_string())).await?;
-    let html: String = response.text().map_err(|err| SipError::TextParseError(err.to_string())).await?;
+    // posle izmene selektora stranica se cita ponovo, iako se na izvoru nije promenila
+    let parser_hash: Option<String> = source.parser_hash();
+    let request_validators: Validators = if parser_hash == state.validators.parser_hash {
+        state.validators.clone()
+    } else {
+        Validators::default()
+    };
+
+    // pribavljanje sa izvora
+    let (html, mut validators): (String, Validators) = match source.fetch(&request_validators).await? {
+        FetchOutcome::NotModified => {
+            logger::log_sync("SPFCH", "NOT MODIFIED");
+            return Ok(0);
+        },
+        FetchOutcome::Modified { body, validators } => (body, validators)
+    };
 
     logger::log_sync("SPFCH", "FETCH ENDED");
 
-    let document: Html = Html::parse_document(&html);
+    // isti sadrzaj kao prosli put, server ne podrzava uslovne zahteve ili ih je zanemario
+    validators.body_hash = Some(hash_bytes(html.bytes()));
+    validators.parser_hash = parser_hash;
+    if validators.body_hash == request_validators.body_hash {
+        logger::log_sync("SPFCH", "UNCHANGED");
+        if validators != state.validators {
+            let mut database = repository.lock().await;
+            database.set_validators(source, &validators)?;
+        }
+        return Ok(0);
+    }
+
+    let page: ParsedPage = match source.parse(&html) {
+        Ok(page) => page,
+        Err(why) => {
+            health.record_failure(source, &why, Some(&html));
+            return Err(why);
+        }
+    };
+
+    for issue in page.issues.iter() {
+        logger::log_sync("PARSE", format!("[{}] Skipped {} post: {} | {}", source.id(), issue.post_type.storage_suffix(), issue.reason, issue.snippet));
+    }
+    health.record_issues(source, &page.issues, &html);
 
-    logger::log_sync("SPFCH", "PARSE ENDED");
+    // bez ijednog ispravnog posta stanje se ne menja
+    if page.posts.is_empty() && !page.issues.is_empty() {
+        let why: SipError = SipError::PostParseError(format!("All {} posts are malformed", page.issues.len()));
+        health.record_failure(source, &why, Some(&html));
+        return Err(why);
+    }
 
-    let left_posts_element: ElementRef = document
-        .select(&ALL_POSTS_SELECTOR)
-        .nth(0)
-        .ok_or_else(|| SipError::PostError("Missing posts".to_string()))?;
+    let partial: bool = !page.issues.is_empty();
+    let posts: Vec<SipPost> = page.posts;
+    health.record_posts(source, posts.len(), &html);
 
-    let right_posts_element: ElementRef = document
-        .select(&ALL_POSTS_SELECTOR)
-        .nth(1)
-        .ok_or_else(|| SipError::PostError("Missing posts".to_string()))?;
+    // stranica bez postova je najcesce promenjen izgled, a ne prazan izvor
+    // stanje se ne menja, inace bi se izgubile veze sa vec poslatim porukama
+    if posts.is_empty() {
+        return Err(SipError::PostParseError("No posts found".to_string()));
+    }
+
+    logger::log_sync("SPFCH", "PARSE ENDED");
+
+    let first_run: bool = state.old_columns.iter().all(|column| column.is_empty()) && state.hashes.is_empty();
+    let mut diff: PostsDiff = diff_posts(state, &posts, partial);
+    let action: BatchAction = batch_action(first_run, diff.fetched.new_posts.len() + diff.fetched.updated_posts.len(), CONFIG.flood_threshold);
+    logger::log_sync("SPFCH", format!(
+        "NEW POSTS: {}, UPDATED POSTS: {}, WITHDRAWN POSTS: {}, PROMOTED POSTS: {}",
+        diff.fetched.new_posts.len(), diff.fetched.updated_posts.len(), diff.fetched.withdrawn_posts.len(), diff.fetched.promoted_posts.len()
+    ));
 
-    // pribavljeni postovi sa sip-a
-    let left_posts: Vec<SipPost> = parse_element_to_posts(left_posts_element, SipPostType::New).map_err(|err| SipError::PostParseError(err.to_string()))?;
-    let right_posts: Vec<SipPost> = parse_element_to_posts(right_posts_element, SipPostType::Important).map_err(|err| SipError::PostParseError(err.to_string()))?;
+    // dopuna sa stranice posta
+    if action != BatchAction::Seed && (CONFIG.full_content || CONFIG.attachments) {
+        fetch_post_pages(diff.fetched.new_posts.iter_mut(), source).await;
+        fetch_post_pages(diff.fetched.updated_posts.iter_mut().map(|update| &mut update.after), source).await;
+    }
 
     logger::log_sync("SPFCH", "SAVING POSTS");
-    database.set("levi_stari", &left_posts).map_err(|err| SipError::StorageError(err.to_string()))?;
-    database.set("desni_stari", &right_posts).map_err(|err| SipError::StorageError(err.to_string()))?;
+    let upload_limit: Option<u64> = CONFIG.reupload_attachments.then_some(CONFIG.reupload_limit);
+    let outcome: SaveOutcome = {
+        let mut database = repository.lock().await;
+        // stanje, arhiva i poruke jednog citanja se upisuju zajedno
+        database.atomically(|database| save_source_state(database, source, &posts, diff, &validators, action, upload_limit))?
+    };
     logger::log_sync("SPFCH", "POSTS SAVED");
 
-    // izdvajanje novih postova
-    let new_left_posts: Vec<SipPost> = left_posts
-        .into_iter()
-        .filter(|left_post| !old_left_posts_links.contains(&left_post.link) && !old_right_posts_links.contains(&left_post.link))
-        .collect();
-    let new_right_posts: Vec<SipPost> = right_posts
-        .into_iter()
-        .filter(|right_post| !old_right_posts_links.contains(&right_post.link) && !old_left_posts_links.contains(&right_post.link))
-        .collect();
+    return Ok(match outcome {
+        SaveOutcome::Enqueued(enqueued) => enqueued,
+        SaveOutcome::Seeded => {
+            logger::log_sync("SPFCH", format!("FIRST RUN, SEEDED {} POSTS", posts.len()));
+            0
+        },
+        SaveOutcome::Held(batch) => {
+            logger::log_sync("SPFCH", format!("HELD BATCH {} WITH {} POSTS", batch.id, batch.post_count));
+            let prefix: &str = &CONFIG.prefix;
+            health.notify(source, "Poruke zadrzane", format!(
+                "Pronadjeno je {} novih ili izmenjenih postova odjednom, vise od dozvoljenih {}.\nPoruke nisu poslate dok ih ne odobris.\n\n`{}sipodobri {}` - salje poruke\n`{}sipodbaci {}` - odbacuje poruke",
+                batch.post_count, CONFIG.flood_threshold, prefix, batch.id, prefix, batch.id
+            ));
+            0
+        }
+    });
+}
 
-    // spajanje postova u jedan niz
-    // uklanjanje dupliciranih novih postova
-    // prioritet se daje desnim postovima
-    // posto su oni vazna obavestenja
-    let new_right_posts_links: HashSet<String> = new_right_posts.iter().map(|new_right_post| new_right_post.link.clone()).collect();
-    let mut final_posts: Vec<SipPost> = new_right_posts;
+fn get_embed_color_from_post(post: &SipPost) -> i32 {
+    return match post.post_type {
+        SipPostType::New => 0x41662D,
+        SipPostType::Important => 0x66F442
+    };
+}
+
+const UPDATED_POST_COLOR: i32 = 0xF4A742;
+
+// discord ogranicenja embeda
+pub const EMBED_TITLE_LIMIT: usize = 256;
+const EMBED_AUTHOR_LIMIT: usize = 256;
+const EMBED_FIELD_NAME_LIMIT: usize = 256;
+pub const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+const EMBED_FIELD_VALUE_LIMIT: usize = 1024;
+// zbir svih tekstova svih embeda u jednoj poruci
+const MESSAGE_EMBEDS_TEXT_LIMIT: usize = 6000;
+const MESSAGE_EMBEDS_LIMIT: usize = 10;
 
-    for new_left_post in new_left_posts {
-        if !new_right_posts_links.contains(&new_left_post.link) {
-            final_posts.push(new_left_post);
+// skracuje tekst na granici reci, ako je rec dovoljno blizu kraja
+pub fn truncate_text(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_string();
+    }
+    if limit == 0 {
+        return String::new();
+    }
+
+    let mut truncated: String = text.chars().take(limit - 1).collect();
+    if let Some(space) = truncated.rfind(char::is_whitespace) {
+        if truncated[..space].chars().count() > limit / 2 {
+            truncated.truncate(space);
         }
     }
+    let trimmed_length: usize = truncated.trim_end().len();
+    truncated.truncate(trimmed_length);
+    truncated.push('…');
+    return truncated;
+}
 
-    logger::log_sync("SPFCH", format!("NEW POSTS: {}", final_posts.len()));
+// skracuje markdown tako da ne ostane prekinut link ili nezatvoreno podebljanje
+fn truncate_markdown(text: &str, limit: usize) -> String {
+    // mesto za zatvaranje podebljanja
+    let mut truncated: String = truncate_text(text, limit - 2);
+    if text.chars().count() > limit - 2 {
+        // oznaka prekinuta na pola (`**tekst*…`) bi ostala kao zvezdica
+        let kept_length: usize = truncated.trim_end_matches('…').trim_end_matches('*').trim_end().len();
+        truncated.truncate(kept_length);
+        truncated.push('…');
+    }
+    if let Some(open) = truncated.rfind('[') {
+        if truncated[open..].find("](").and_then(|middle| truncated[open + middle..].find(')')).is_none() {
+            truncated.truncate(open);
+            let trimmed_length: usize = truncated.trim_end().len();
+            truncated.truncate(trimmed_length);
+            truncated.push('…');
+        }
+    }
+    if truncated.matches("**").count() % 2 == 1 {
+        truncated.push_str("**");
+    }
+    return truncated;
+}
 
-    return Ok(final_posts);
+// predugacak opis se skracuje i zavrsava linkom ka celom postu
+fn read_more(link: &str) -> String {
+    return format!("\n\n[Прочитај више]({})", link);
 }
 
-fn get_embed_author_name_from_post(post: &SipPost) -> String {
-    return match post.post_type {
-        SipPostType::New => "Најновије вести".to_string(),
-        SipPostType::Important => "Важна обавештења".to_string()
+fn description_with_read_more(text: &str, link: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_string();
+    }
+    let read_more: String = read_more(link);
+    let read_more_length: usize = read_more.chars().count();
+    if limit <= read_more_length + 2 {
+        return truncate_text(text, limit);
+    }
+    return format!("{}{}", truncate_markdown(text, limit - read_more_length), read_more);
+}
+
+fn text_length(value: &Value) -> usize {
+    return value.as_str().map(|text| text.chars().count()).unwrap_or(0);
+}
+
+// broj karaktera embeda koji discord racuna u ogranicenje od 6000 po poruci
+pub fn embed_text_length(embed: &Value) -> usize {
+    let fields_length: usize = embed["fields"]
+        .as_array()
+        .map(|fields| fields.iter().map(|field| text_length(&field["name"]) + text_length(&field["value"])).sum())
+        .unwrap_or(0);
+
+    return text_length(&embed["title"])
+        + text_length(&embed["description"])
+        + text_length(&embed["author"]["name"])
+        + text_length(&embed["footer"]["text"])
+        + fields_length;
+}
+
+// opis embeda se dodatno skracuje ako bi embed sam presao ogranicenje poruke
+fn fit_embed_description(embed: &mut Value, full_description: &str, link: &str) {
+    // mesto za oznaku naslova ako post kasnije bude povucen, inace izmena ne bi prosla
+    let without_description: usize = embed_text_length(embed) - text_length(&embed["description"]) + WITHDRAWN_TITLE_PREFIX.chars().count();
+    let budget: usize = MESSAGE_EMBEDS_TEXT_LIMIT.saturating_sub(without_description).min(EMBED_DESCRIPTION_LIMIT);
+    if text_length(&embed["description"]) > budget {
+        embed["description"] = json!(description_with_read_more(full_description, link, budget));
+    }
+}
+
+// embed koji zamenjuje embed vec poslate poruke
+// opis se skracuje tako da poruka sa ostalim embedima (`other_embeds_length` karaktera) ne predje ogranicenje
+pub fn fit_embed_to_message(embed: &mut Value, other_embeds_length: usize) {
+    let without_description: usize = embed_text_length(embed) - text_length(&embed["description"]) + WITHDRAWN_TITLE_PREFIX.chars().count();
+    let budget: usize = MESSAGE_EMBEDS_TEXT_LIMIT.saturating_sub(other_embeds_length + without_description).min(EMBED_DESCRIPTION_LIMIT);
+    let description: String = embed["description"].as_str().unwrap_or_default().to_string();
+    if description.chars().count() <= budget {
+        return;
+    }
+
+    let link: String = embed["url"].as_str().unwrap_or_default().to_string();
+    // vec skracen opis se ne skracuje zajedno sa linkom na kraju
+    let text: &str = description.strip_suffix(&read_more(&link)).unwrap_or(&description);
+    embed["description"] = json!(description_with_read_more(text, &link, budget));
+}
+
+// embedi u poruke, najvise 10 po poruci i 6000 karaktera ukupno
+fn batch_embeds<T: Clone>(embeds: &[(Value, T)]) -> Vec<Vec<(Value, T)>> {
+    let mut batches: Vec<Vec<(Value, T)>> = Vec::<Vec<(Value, T)>>::new();
+    let mut batch: Vec<(Value, T)> = Vec::<(Value, T)>::new();
+    let mut batch_length: usize = 0;
+
+    for (embed, extra) in embeds {
+        // mesto za oznaku naslova ako post kasnije bude povucen
+        let length: usize = embed_text_length(embed) + WITHDRAWN_TITLE_PREFIX.chars().count();
+        if !batch.is_empty() && (batch.len() == MESSAGE_EMBEDS_LIMIT || batch_length + length > MESSAGE_EMBEDS_TEXT_LIMIT) {
+            batches.push(std::mem::take(&mut batch));
+            batch_length = 0;
+        }
+        batch.push((embed.clone(), extra.clone()));
+        batch_length += length;
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+
+    return batches;
+}
+
+// prazna vrednost polja nije dozvoljena
+fn field_value(text: &str) -> String {
+    if text.trim().is_empty() {
+        return "-".to_string();
+    }
+    return truncate_text(text, EMBED_FIELD_VALUE_LIMIT);
+}
+
+fn field_name(text: &str) -> String {
+    if text.trim().is_empty() {
+        return "-".to_string();
+    }
+    return truncate_text(text, EMBED_FIELD_NAME_LIMIT);
+}
+
+// discord dozvoljava najvise 25 polja u embedu
+const EMBED_FIELDS_LIMIT: usize = 25;
+
+// otvara stranicu svakog posta, u slucaju greske ostaje izvod bez priloga
+pub async fn fetch_post_pages<'a>(posts: impl Iterator<Item = &'a mut SipPost>, source: &dyn PostSource) {
+    for post in posts {
+        match source.fetch_post_page(post).await {
+            Ok(Some(page)) => {
+                if CONFIG.full_content {
+                    post.full_content = page.content;
+                }
+                if CONFIG.attachments {
+                    post.attachments = page.attachments;
+                }
+            },
+            Ok(None) => {},
+            Err(why) => logger::log("ERR", format!("[{}] {}: {}", source.id(), post.link, why)).await
+        };
+    }
+}
+
+fn format_file_size(size: u64) -> String {
+    return match size {
+        0..=1023 => format!("{} B", size),
+        1024..=1048575 => format!("{:.1} KB", size as f64 / 1024.0),
+        _ => format!("{:.1} MB", size as f64 / 1048576.0)
     };
 }
 
-fn get_embed_color_from_post(post: &SipPost) -> i32 {
-    return match post.post_type {
-        SipPostType::New => 0x41662D,
-        SipPostType::Important => 0x66F442
+fn attachment_field_value(attachment: &SipAttachment) -> String {
+    return match attachment.size {
+        Some(size) => format!("[Preuzmi ({})]({})", format_file_size(size), attachment.url),
+        None => format!("[Preuzmi]({})", attachment.url)
     };
 }
 
-pub fn create_embed_from_post(post: SipPost) -> Value {
-    return Embed::fake(|e|
+// prilozi koji se ponovo salju kao fajlovi u poruci
+// discord ogranicava zbir velicina svih fajlova poruke, pa se fajlovi preko ogranicenja izostavljaju
+fn attachments_to_upload(post: &SipPost, limit: u64) -> Vec<SipAttachment> {
+    let mut total_size: u64 = 0;
+    let mut uploads: Vec<SipAttachment> = Vec::<SipAttachment>::new();
+    for attachment in post.attachments.iter() {
+        // velicina nepoznata do preuzimanja, proverava se pri slanju
+        let size: u64 = attachment.size.unwrap_or(0);
+        if total_size + size > limit {
+            continue;
+        }
+        total_size += size;
+        uploads.push(attachment.clone());
+    }
+    return uploads;
+}
+
+// vreme objave kao discord timestamp, inace originalni tekst
+fn published_field_value(post: &SipPost) -> String {
+    if let Some(published_at) = &post.published_at {
+        return discord_timestamp(published_at);
+    }
+    if post.date.is_empty() {
+        return "-".to_string();
+    }
+    return post.date.clone();
+}
+
+pub fn create_embed_from_post(post: SipPost, source: &dyn PostSource) -> Value {
+    let mut embed: Value = Embed::fake(|e|
         e
             .author(|a|
                 a
-                    .name(get_embed_author_name_from_post(&post))
+                    .name(truncate_text(&source.author_name(&post), EMBED_AUTHOR_LIMIT))
+                    .url(source.url())
             )
             .color(get_embed_color_from_post(&post))
-            .title(post.title)
-            .description(post.content)
-            .url(post.link)
+            .field("Објављено", published_field_value(&post), true)
+            .title(truncate_text(&post.title, EMBED_TITLE_LIMIT))
+            .description(description_with_read_more(post.description(), &post.link, EMBED_DESCRIPTION_LIMIT))
+            .url(&post.link)
             .thumbnail("https://i.imgur.com/dyu12dZ.png")
+            .fields(
+                post.attachments
+                    .iter()
+                    .take(EMBED_FIELDS_LIMIT - 1)
+                    .map(|attachment| (field_name(&attachment.name), attachment_field_value(attachment), false))
+            )
     );
+    fit_embed_description(&mut embed, post.description(), &post.link);
+    return embed;
 }
 
-pub async fn fetcher_main(http: Http) {
-    let mut interval = tokio::time::interval(Duration::from_secs(15 * 60)); // 15 minuta
+// embed sa razlikama naslova i sadrzaja pre i posle izmene
+pub fn create_update_embed_from_post(update: PostUpdate, source: &dyn PostSource) -> Value {
+    let after: SipPost = update.after;
+    let mut fields: Vec<(String, String, bool)> = Vec::<(String, String, bool)>::new();
 
-    loop {
-        interval.tick().await;
+    if let Some(before) = &update.before {
+        if before.title != after.title {
+            fields.push(("Наслов (пре)".to_string(), field_value(&before.title), false));
+            fields.push(("Наслов (после)".to_string(), field_value(&after.title), false));
+        }
+        if before.content != after.content {
+            fields.push(("Садржај (пре)".to_string(), field_value(&before.content), false));
+            fields.push(("Садржај (после)".to_string(), field_value(&after.content), false));
+        }
+    }
 
-        logger::log("SPFCH", "TASK STARTED").await;
+    let mut embed: Value = Embed::fake(|e| {
+        e
+            .author(|a|
+                a
+                    .name(truncate_text(&format!("{} (измењено)", source.author_name(&after)), EMBED_AUTHOR_LIMIT))
+                    .url(source.url())
+            )
+            .color(UPDATED_POST_COLOR)
+            .title(truncate_text(&after.title, EMBED_TITLE_LIMIT))
+            .url(&after.link)
+            .thumbnail("https://i.imgur.com/dyu12dZ.png")
+            .fields(fields);
+
+        // bez stare verzije nema razlika, prikazuje se novi sadrzaj
+        if update.before.is_none() {
+            e.description(description_with_read_more(after.description(), &after.link, EMBED_DESCRIPTION_LIMIT));
+        }
 
-        let webhooks: Vec<Webhook>;
-        let embed_posts: Vec<Value>;
+        e
+    });
+    if update.before.is_none() {
+        fit_embed_description(&mut embed, after.description(), &after.link);
+    }
+    return embed;
+}
 
-        {
-            let mut database = DATABASE.lock().await;
-            webhooks = database.get::<Vec<Webhook>>("sip_hooks").unwrap_or(Vec::<Webhook>::new());
-            embed_posts = fetch_posts(&mut database).await.unwrap_or(Vec::<SipPost>::new()).into_iter().map(|sip_post| create_embed_from_post(sip_post)).collect();
+// embed za post premesten u vazna obavestenja
+pub fn create_promotion_embed_from_post(post: SipPost, source: &dyn PostSource) -> Value {
+    let mut embed: Value = create_embed_from_post(post, source);
+    embed["author"]["name"] = json!(truncate_text(&format!("{} (премештено из: {})", SipPostType::Important.display_name(), SipPostType::New.display_name()), EMBED_AUTHOR_LIMIT));
+    return embed;
+}
+
+// poruke i izmene poruka za sve promene jednog citanja izvora
+// postovi sa fajlovima za slanje idu u zasebne poruke
+// ostali se grupisu, najvise 10 embeda i 6000 karaktera po poruci
+// `upload_limit` je najveca ukupna velicina fajlova po poruci, None ako se fajlovi ne salju ponovo
+pub fn create_deliveries(fetched: FetchedPosts, source: &dyn PostSource, upload_limit: Option<u64>) -> Vec<Delivery> {
+    let mut deliveries: Vec<Delivery> = Vec::<Delivery>::new();
+    let mut embed_posts: Vec<(Value, Option<String>)> = Vec::<(Value, Option<String>)>::new();
+
+    for update in fetched.updated_posts {
+        deliveries.push(Delivery::Edit { link: update.after.key.clone(), edit: EmbedEdit::Replace(create_embed_from_post(update.after.clone(), source)) });
+        embed_posts.push((create_update_embed_from_post(update, source), None));
+    }
+    for withdrawn_post in fetched.withdrawn_posts {
+        deliveries.push(Delivery::Edit { link: withdrawn_post.key, edit: EmbedEdit::Withdraw });
+    }
+
+    // premestanje u vazna obavestenja ide u zasebnu poruku zbog pominjanja uloge
+    let promoted_embeds: Vec<(Value, Option<String>)> = fetched.promoted_posts
+        .into_iter()
+        .map(|post| {
+            let key: String = post.key.clone();
+            (create_promotion_embed_from_post(post, source), Some(key))
+        })
+        .collect();
+    for batch in batch_embeds(&promoted_embeds) {
+        let (embeds, links): (Vec<Value>, Vec<Option<String>>) = batch.into_iter().unzip();
+        deliveries.push(Delivery::Send(WebhookMessage { embeds, links, uploads: Vec::<SipAttachment>::new(), ping: true }));
+    }
+
+    for sip_post in fetched.new_posts {
+        let uploads: Vec<SipAttachment> = match upload_limit {
+            Some(limit) => attachments_to_upload(&sip_post, limit),
+            None => Vec::<SipAttachment>::new()
+        };
+        let key: String = sip_post.key.clone();
+        let embed: Value = create_embed_from_post(sip_post, source);
+        if uploads.is_empty() {
+            embed_posts.push((embed, Some(key)));
+        } else {
+            deliveries.push(Delivery::Send(WebhookMessage { embeds: vec![embed], links: vec![Some(key)], uploads, ping: false }));
         }
+    }
 
-        let webhooks_count: usize = webhooks.len();
-        let posts_count: usize = embed_posts.len();
-        if webhooks_count == 0 || posts_count == 0  {
-            logger::log("SPFCH", "TASK ENDED").await;
-            continue;
+    for batch in batch_embeds(&embed_posts) {
+        let (embeds, links): (Vec<Value>, Vec<Option<String>>) = batch.into_iter().unzip();
+        deliveries.push(Delivery::Send(WebhookMessage { embeds, links, uploads: Vec::<SipAttachment>::new(), ping: false }));
+    }
+
+    return deliveries;
+}
+
+pub async fn fetcher_main(sources: Vec<Box<dyn PostSource>>, schedule: PollSchedule, http: Arc<Http>, owner_id: UserId, repository: SharedRepository) {
+    let mut first_tick: bool = true;
+    let mut health: HealthMonitor = HealthMonitor::new(CONFIG.health_failure_threshold, CONFIG.health_drop_percent, HTML_DIRECTORY);
+
+    loop {
+        // prvo citanje odmah po pokretanju
+        if !first_tick {
+            let delay: Duration = next_poll_delay(&schedule);
+            logger::log("SPFCH", format!("NEXT POLL IN {} s", delay.as_secs())).await;
+            tokio::time::sleep(delay).await;
         }
+        first_tick = false;
 
-        // jedan webhook prima 10 embeda
-        // moramo da podelimo posts na podnizove
-        // od po 10 elementa
-        // tako za svaki webhook
+        logger::log("SPFCH", "TASK STARTED").await;
+        selectors::reload_if_changed().await;
 
-        let mut chunks: Vec<Value> = Vec::<Value>::new();
-        for ten_chunk in embed_posts.chunks(10) {
-            chunks.push(
-                json!({"embeds": ten_chunk.to_owned()})
-            );
+        // kopija pre upisa novog stanja
+        let backup: Result<(), SipError> = repository.lock().await.backup();
+        if let Err(why) = backup {
+            logger::log("ERR", why.to_string()).await;
         }
-        let all_sessions_embeds: Vec<&JsonMap> = chunks.iter().filter_map(|x| x.as_object()).collect();
-
-        for webhook in webhooks {
-            if let Some(webhook_token) = webhook.token {
-                for per_session_embed in all_sessions_embeds.iter() {
-                    match http.execute_webhook(webhook.id.0, &webhook_token, true, &per_session_embed).await {
-                        Ok(_) => {},
-                        Err(why) => {
-                            logger::log("ERR", why.to_string()).await;
-                        }
-                    };
+
+        let mut enqueued: usize = 0;
+        for source in sources.iter() {
+            match fetch_posts(source.as_ref(), &mut health, &repository).await {
+                Ok(source_enqueued) => enqueued += source_enqueued,
+                Err(why) => {
+                    // greske pri parsiranju su vec zabelezene zajedno sa stranicom
+                    if let SipError::FetchError(_, _) = why {
+                        health.record_failure(source.as_ref(), &why, None);
+                    }
+                    logger::log("ERR", format!("[{}] {}", source.id(), why)).await;
                 }
+            };
+        }
+
+        for alert in health.take_alerts() {
+            if let Err(why) = send_alert(&http, owner_id, &alert).await {
+                logger::log("HLTH", format!("Can't send alert: {}", why)).await;
             }
         }
 
+        if enqueued > 0 {
+            logger::log("SPFCH", format!("ENQUEUED: {}", enqueued)).await;
+            DELIVERY_NOTIFY.notify_one();
+        }
+
         logger::log("SPFCH", "TASK ENDED").await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::DeliveryStatus;
+    use crate::delivery::OutboxEntry;
+    use crate::repository::MemoryRepository;
+    use crate::sources::SipSource;
+    use crate::test_fixtures::{post, repository};
+
+    // jedno citanje izvora, kao u fetch_posts
+    fn poll(database: &mut dyn Repository, posts: &[SipPost], action: BatchAction) -> SaveOutcome {
+        let state: SourceState = read_source_state(database, &SipSource).unwrap();
+        let diff: PostsDiff = diff_posts(state, posts, false);
+        return database.atomically(|database| save_source_state(database, &SipSource, posts, diff, &Validators::default(), action, None)).unwrap();
+    }
+
+    #[test]
+    fn first_poll_seeds_state_without_messages() {
+        let mut repository: MemoryRepository = repository();
+        let posts: Vec<SipPost> = vec![post(SipPostType::New, 2, "Drugi"), post(SipPostType::New, 1, "Prvi"), post(SipPostType::Important, 3, "Vazno")];
+
+        assert!(matches!(poll(&mut repository, &posts, BatchAction::Seed), SaveOutcome::Seeded));
+
+        let state: SourceState = read_source_state(&repository, &SipSource).unwrap();
+        assert_eq!(state.old_columns.iter().map(Vec::len).collect::<Vec<usize>>(), vec![2, 1]);
+        assert_eq!(state.hashes.len(), 3);
+        assert!(repository.outbox().unwrap().is_empty());
+        assert_eq!(repository.archived_post(&posts[0].key).unwrap().unwrap().delivery_status, DeliveryStatus::Seeded);
+    }
+
+    #[test]
+    fn unchanged_page_has_nothing_to_send() {
+        let mut repository: MemoryRepository = repository();
+        let posts: Vec<SipPost> = vec![post(SipPostType::New, 2, "Drugi"), post(SipPostType::New, 1, "Prvi")];
+        poll(&mut repository, &posts, BatchAction::Seed);
+
+        let diff: PostsDiff = diff_posts(read_source_state(&repository, &SipSource).unwrap(), &posts, false);
+        assert!(diff.fetched.new_posts.is_empty());
+        assert!(diff.fetched.updated_posts.is_empty());
+        assert!(diff.fetched.withdrawn_posts.is_empty());
+        assert!(diff.fetched.promoted_posts.is_empty());
+    }
+
+    #[test]
+    fn new_edited_and_withdrawn_posts_are_queued() {
+        let mut repository: MemoryRepository = repository();
+        poll(&mut repository, &[post(SipPostType::New, 3, "Treci"), post(SipPostType::New, 2, "Drugi"), post(SipPostType::New, 1, "Prvi")], BatchAction::Seed);
+
+        // 4 je nov, 3 je izmenjen, 2 je povucen, a 1 je ostao ispod njega
+        let posts: Vec<SipPost> = vec![post(SipPostType::New, 4, "Cetvrti"), post(SipPostType::New, 3, "Treci, izmenjen"), post(SipPostType::New, 1, "Prvi")];
+        let diff: PostsDiff = diff_posts(read_source_state(&repository, &SipSource).unwrap(), &posts, false);
+        assert_eq!(diff.fetched.new_posts.iter().map(|post| post.title.as_str()).collect::<Vec<&str>>(), vec!["Cetvrti"]);
+        assert_eq!(diff.fetched.updated_posts.len(), 1);
+        assert_eq!(diff.fetched.withdrawn_posts.iter().map(|post| post.title.as_str()).collect::<Vec<&str>>(), vec!["Drugi"]);
+
+        assert!(matches!(poll(&mut repository, &posts, BatchAction::Deliver), SaveOutcome::Enqueued(3)));
+
+        let outbox: Vec<OutboxEntry> = repository.outbox().unwrap();
+        let withdrawn_key: String = post(SipPostType::New, 2, "Drugi").key;
+        assert!(outbox.iter().all(|entry| entry.webhook_id == 1));
+        assert!(outbox.iter().any(|entry| matches!(&entry.delivery, Delivery::Edit { link, edit: EmbedEdit::Withdraw } if *link == withdrawn_key)));
+        assert!(outbox.iter().any(|entry| matches!(&entry.delivery, Delivery::Send(message) if message.links.contains(&Some(posts[0].key.clone())))));
+
+        assert_eq!(repository.archived_post(&posts[0].key).unwrap().unwrap().delivery_status, DeliveryStatus::Queued);
+        assert!(repository.archived_post(&withdrawn_key).unwrap().unwrap().withdrawn_at.is_some());
+        assert_eq!(repository.archived_post(&posts[1].key).unwrap().unwrap().revisions.len(), 2);
+    }
+
+    #[test]
+    fn partial_page_does_not_withdraw_posts() {
+        let mut repository: MemoryRepository = repository();
+        poll(&mut repository, &[post(SipPostType::New, 2, "Drugi"), post(SipPostType::New, 1, "Prvi")], BatchAction::Seed);
+
+        let diff: PostsDiff = diff_posts(read_source_state(&repository, &SipSource).unwrap(), &[post(SipPostType::New, 1, "Prvi")], true);
+        assert!(diff.fetched.withdrawn_posts.is_empty());
+    }
+
+    #[test]
+    fn promoted_post_is_announced_again() {
+        let mut repository: MemoryRepository = repository();
+        poll(&mut repository, &[post(SipPostType::New, 1, "Prvi")], BatchAction::Seed);
+
+        let posts: Vec<SipPost> = vec![post(SipPostType::Important, 1, "Prvi")];
+        let diff: PostsDiff = diff_posts(read_source_state(&repository, &SipSource).unwrap(), &posts, false);
+        assert_eq!(diff.fetched.promoted_posts.len(), 1);
+        assert!(diff.fetched.new_posts.is_empty());
+    }
+
+    #[test]
+    fn failed_save_keeps_previous_state() {
+        let mut repository: MemoryRepository = repository();
+        let posts: Vec<SipPost> = vec![post(SipPostType::New, 1, "Prvi")];
+        poll(&mut repository, &posts, BatchAction::Seed);
+
+        let database: &mut dyn Repository = &mut repository;
+        let state: SourceState = read_source_state(database, &SipSource).unwrap();
+        let newer: Vec<SipPost> = vec![post(SipPostType::New, 2, "Drugi"), post(SipPostType::New, 1, "Prvi")];
+        let diff: PostsDiff = diff_posts(state, &newer, false);
+        let result: Result<SaveOutcome, SipError> = database.atomically(|database| {
+            save_source_state(database, &SipSource, &newer, diff, &Validators::default(), BatchAction::Deliver, None)?;
+            return Err(SipError::StorageError("disk is full".to_string()));
+        });
+
+        assert!(result.is_err());
+        assert!(database.outbox().unwrap().is_empty());
+        assert_eq!(read_source_state(database, &SipSource).unwrap().old_columns[0].len(), 1);
+    }
+
+    #[test]
+    fn truncate_text_cuts_at_word_boundary() {
+        assert_eq!(truncate_text("kratak tekst", 20), "kratak tekst");
+        assert_eq!(truncate_text("prva druga treca cetvrta", 15), "prva druga…");
+        assert_eq!(truncate_text("jednadugackarecbezrazmaka", 10), "jednaduga…");
+        assert_eq!(truncate_text("ćirilica ђурђевдан", 12), "ćirilica…");
+        assert_eq!(truncate_text("tekst", 0), "");
+    }
+
+    #[test]
+    fn truncate_markdown_closes_bold() {
+        let truncated: String = truncate_markdown("Pocetak **veoma dugacko podebljano obavestenje** kraj", 30);
+        assert_eq!(truncated, "Pocetak **veoma dugacko…**");
+        assert!(truncated.chars().count() <= 30);
+        assert_eq!(truncated.matches("**").count() % 2, 0);
+    }
+
+    #[test]
+    fn truncate_markdown_drops_half_of_a_marker() {
+        // skracivanje pada izmedju dve zvezdice zatvaranja
+        let truncated: String = truncate_markdown("**bold**nastavak", 10);
+        assert_eq!(truncated, "**bold…**");
+        assert!(!truncated.contains("*…"));
+    }
+
+    #[test]
+    fn truncate_markdown_drops_cut_link() {
+        assert_eq!(truncate_markdown("Vidi [raspored ispita](https://sip.elfak.ni.ac.rs/raspored) ovde", 40), "Vidi…");
+        assert_eq!(
+            truncate_markdown("[raspored](https://sip.elfak.ni.ac.rs/r) i jos mnogo teksta posle", 45),
+            "[raspored](https://sip.elfak.ni.ac.rs/r)…"
+        );
+    }
+
+    #[test]
+    fn read_more_link_fits_the_limit() {
+        let text: String = "rec ".repeat(2000);
+        let description: String = description_with_read_more(&text, "https://sip.elfak.ni.ac.rs/vesti/1", EMBED_DESCRIPTION_LIMIT);
+        assert!(description.chars().count() <= EMBED_DESCRIPTION_LIMIT);
+        assert!(description.ends_with("(https://sip.elfak.ni.ac.rs/vesti/1)"));
+    }
+
+    // embed sa opisom tacne duzine, bez ostalih tekstova
+    fn embed_of_length(length: usize) -> (Value, Option<String>) {
+        return (json!({"description": "a".repeat(length)}), None);
+    }
+
+    #[test]
+    fn batch_embeds_respects_6000_characters() {
+        let prefix_length: usize = WITHDRAWN_TITLE_PREFIX.chars().count();
+        let half: usize = MESSAGE_EMBEDS_TEXT_LIMIT / 2 - prefix_length;
+
+        // tacno 6000 zajedno sa oznakama povlacenja
+        let batches: Vec<Vec<(Value, Option<String>)>> = batch_embeds(&[embed_of_length(half), embed_of_length(half)]);
+        assert_eq!(batches.len(), 1);
+
+        // jedan karakter preko
+        let batches: Vec<Vec<(Value, Option<String>)>> = batch_embeds(&[embed_of_length(half), embed_of_length(half + 1)]);
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<usize>>(), vec![1, 1]);
+    }
+
+    #[test]
+    fn batch_embeds_respects_10_embeds() {
+        let embeds: Vec<(Value, Option<String>)> = (0..25).map(|_| embed_of_length(10)).collect();
+        let batches: Vec<Vec<(Value, Option<String>)>> = batch_embeds(&embeds);
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<usize>>(), vec![10, 10, 5]);
+    }
+
+    #[test]
+    fn uploads_fit_the_message_limit() {
+        let mut large_post: SipPost = post(SipPostType::New, 1, "Prilozi");
+        large_post.attachments = [Some(6), Some(5), None, Some(4)]
+            .iter()
+            .enumerate()
+            .map(|(index, size)| SipAttachment { name: format!("{}.pdf", index), url: format!("https://sip.elfak.ni.ac.rs/files/{}.pdf", index), size: size.map(|size: u64| size * 1024 * 1024) })
+            .collect();
+
+        // 6 MB i 4 MB staju u 10 MB, 5 MB ne staje posle prvog fajla
+        let uploads: Vec<SipAttachment> = attachments_to_upload(&large_post, 10 * 1024 * 1024);
+        assert_eq!(uploads.iter().map(|upload| upload.name.as_str()).collect::<Vec<&str>>(), vec!["0.pdf", "2.pdf", "3.pdf"]);
+    }
+
+    #[test]
+    fn long_post_fits_one_message_even_when_withdrawn() {
+        let mut long_post: SipPost = post(SipPostType::New, 1, &"Naslov ".repeat(20));
+        long_post.full_content = Some("Dugacak sadrzaj posta. ".repeat(400));
+        // prilozi sa dugim imenima, pa opis mora da bude kraci od 4096
+        long_post.attachments = (0..8)
+            .map(|index| SipAttachment { name: format!("{} {}.pdf", "Raspored ispita ".repeat(12), index), url: format!("https://sip.elfak.ni.ac.rs/files/{}.pdf", index), size: None })
+            .collect();
+
+        let mut embed: Value = create_embed_from_post(long_post, &SipSource);
+        let title: String = embed["title"].as_str().unwrap().to_string();
+        embed["title"] = json!(truncate_text(&format!("{}{}", WITHDRAWN_TITLE_PREFIX, title), EMBED_TITLE_LIMIT));
+
+        assert!(text_length(&embed["description"]) < EMBED_DESCRIPTION_LIMIT);
+        assert!(embed_text_length(&embed) <= MESSAGE_EMBEDS_TEXT_LIMIT);
+    }
+}
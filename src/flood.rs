@@ -0,0 +1,191 @@
+use chrono::Utc;
+use crate::repository::Repository;
+use serde::{Serialize, Deserialize};
+
+use crate::archive::{self, DeliveryStatus};
+use crate::delivery::{delivery_links, enqueue, Delivery};
+use crate::errors::SipError;
+
+// zadrzane poruke koje cekaju odobrenje vlasnika
+pub const HELD_BATCHES_KEY: &str = "sip_held";
+pub const HELD_NEXT_ID_KEY: &str = "sip_held_next_id";
+
+// sta se radi sa porukama posle citanja
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchAction {
+    Deliver,
+    // prvo citanje, stanje se upisuje bez slanja
+    Seed,
+    // previse novih postova odjednom, poruke cekaju odobrenje
+    Hold
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HeldBatch {
+    pub id: u64,
+    pub source_id: String,
+    // broj novih i izmenjenih postova
+    pub post_count: usize,
+    // unix vreme zadrzavanja
+    pub held_at: i64,
+    pub deliveries: Vec<Delivery>
+}
+
+// prazno stanje znaci da izvor nikad nije procitan
+// broj postova iznad SIP_FLOOD_THRESHOLD (0 iskljucuje proveru) zadrzava poruke
+pub fn batch_action(first_run: bool, post_count: usize, threshold: usize) -> BatchAction {
+    if first_run {
+        return BatchAction::Seed;
+    }
+    if threshold > 0 && post_count > threshold {
+        return BatchAction::Hold;
+    }
+    return BatchAction::Deliver;
+}
+
+pub fn hold_batch(database: &mut dyn Repository, source_id: &str, post_count: usize, deliveries: Vec<Delivery>) -> Result<HeldBatch, SipError> {
+    let id: u64 = database.next_held_batch_id()?;
+    let batch: HeldBatch = HeldBatch {
+        id,
+        source_id: source_id.to_string(),
+        post_count,
+        held_at: Utc::now().timestamp(),
+        deliveries
+    };
+
+    database.save_held_batch(&batch)?;
+
+    return Ok(batch);
+}
+
+// kljucevi postova za koje se salju nove poruke
+fn batch_keys(batch: &HeldBatch) -> Vec<String> {
+    return batch.deliveries
+        .iter()
+        .filter(|delivery| matches!(delivery, Delivery::Send(_)))
+        .flat_map(delivery_links)
+        .collect();
+}
+
+// upisuje zadrzane poruke u outbox, vraca broj upisanih poruka
+pub fn approve_batch(database: &mut dyn Repository, id: u64) -> Result<Option<usize>, SipError> {
+    let batch: HeldBatch = match database.take_held_batch(id)? {
+        Some(batch) => batch,
+        None => return Ok(None)
+    };
+    archive::set_status(database, &batch_keys(&batch), DeliveryStatus::Queued)?;
+    return Ok(Some(enqueue(database, batch.deliveries)?));
+}
+
+pub fn discard_batch(database: &mut dyn Repository, id: u64) -> Result<Option<HeldBatch>, SipError> {
+    let batch: Option<HeldBatch> = database.take_held_batch(id)?;
+    if let Some(batch) = &batch {
+        archive::set_status(database, &batch_keys(batch), DeliveryStatus::Discarded)?;
+    }
+    return Ok(batch);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::archive::record_posts;
+    use crate::delivery::{OutboxEntry, WebhookMessage};
+    use crate::fetcher::{SipAttachment, SipPost, SipPostType};
+    use crate::repository::MemoryRepository;
+    use crate::test_fixtures::repository;
+
+    fn post(id: u32) -> SipPost {
+        return crate::test_fixtures::post(SipPostType::New, id, &format!("Post {}", id));
+    }
+
+    // zadrzane poruke za postove 1 i 2, kao posle citanja sa previse novih postova
+    fn held_repository() -> (MemoryRepository, HeldBatch) {
+        let mut repository: MemoryRepository = repository();
+
+        let posts: Vec<SipPost> = vec![post(1), post(2)];
+        let keys: HashSet<String> = posts.iter().map(|post| post.key.clone()).collect();
+        let deliveries: Vec<Delivery> = vec![Delivery::Send(WebhookMessage {
+            embeds: vec![json!({"title": "Post 1"}), json!({"title": "Post 2"})],
+            links: posts.iter().map(|post| Some(post.key.clone())).collect(),
+            uploads: Vec::<SipAttachment>::new(),
+            ping: false
+        })];
+
+        record_posts(&mut repository, "sip", &posts, &keys, &HashSet::<String>::new(), BatchAction::Hold).unwrap();
+        let batch: HeldBatch = hold_batch(&mut repository, "sip", posts.len(), deliveries).unwrap();
+        return (repository, batch);
+    }
+
+    fn status(repository: &MemoryRepository, id: u32) -> DeliveryStatus {
+        return repository.archived_post(&post(id).key).unwrap().unwrap().delivery_status;
+    }
+
+    #[test]
+    fn flood_threshold() {
+        assert_eq!(batch_action(true, 100, 5), BatchAction::Seed);
+        assert_eq!(batch_action(false, 5, 5), BatchAction::Deliver);
+        assert_eq!(batch_action(false, 6, 5), BatchAction::Hold);
+        assert_eq!(batch_action(false, 100, 0), BatchAction::Deliver);
+    }
+
+    #[test]
+    fn held_batches_get_increasing_ids() {
+        let (mut repository, batch) = held_repository();
+        let second: HeldBatch = hold_batch(&mut repository, "sip", 1, Vec::<Delivery>::new()).unwrap();
+
+        assert_eq!(second.id, batch.id + 1);
+        assert_eq!(repository.held_batches().unwrap().len(), 2);
+        assert!(repository.outbox().unwrap().is_empty());
+        assert_eq!(status(&repository, 1), DeliveryStatus::Held);
+    }
+
+    #[test]
+    fn saving_a_batch_again_replaces_it() {
+        let (mut repository, mut batch) = held_repository();
+        batch.post_count = 3;
+        repository.save_held_batch(&batch).unwrap();
+
+        assert_eq!(repository.held_batches().unwrap().iter().map(|held| held.post_count).collect::<Vec<usize>>(), vec![3]);
+    }
+
+    #[test]
+    fn approve_batch_enqueues_messages() {
+        let (mut repository, batch) = held_repository();
+
+        let enqueued: Option<usize> = approve_batch(&mut repository, batch.id).unwrap();
+
+        assert_eq!(enqueued, Some(1));
+        let outbox: Vec<OutboxEntry> = repository.outbox().unwrap();
+        assert_eq!(outbox.len(), 1);
+        assert_eq!(outbox[0].webhook_id, 1);
+        assert!(repository.held_batches().unwrap().is_empty());
+        assert_eq!(status(&repository, 1), DeliveryStatus::Queued);
+        assert_eq!(status(&repository, 2), DeliveryStatus::Queued);
+    }
+
+    #[test]
+    fn discard_batch_drops_messages() {
+        let (mut repository, batch) = held_repository();
+
+        let discarded: Option<HeldBatch> = discard_batch(&mut repository, batch.id).unwrap();
+
+        assert_eq!(discarded.map(|discarded| discarded.id), Some(batch.id));
+        assert!(repository.outbox().unwrap().is_empty());
+        assert!(repository.held_batches().unwrap().is_empty());
+        assert_eq!(status(&repository, 1), DeliveryStatus::Discarded);
+    }
+
+    #[test]
+    fn unknown_batch_changes_nothing() {
+        let (mut repository, batch) = held_repository();
+
+        assert_eq!(approve_batch(&mut repository, batch.id + 1).unwrap(), None);
+        assert!(discard_batch(&mut repository, batch.id + 1).unwrap().is_none());
+        assert_eq!(repository.held_batches().unwrap().len(), 1);
+        assert_eq!(status(&repository, 1), DeliveryStatus::Held);
+    }
+}
@@ -0,0 +1,366 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use chrono::Utc;
+use serenity::futures::TryFutureExt;
+use serenity::http::Http;
+use serenity::model::prelude::{AttachmentType, ChannelId, UserId};
+
+use crate::config::{CONFIG, DISCORD_UPLOAD_LIMIT};
+use crate::errors::{DiscordError, SipError};
+use crate::logger;
+use crate::sources::{ParseIssue, PostSource};
+
+// najvise neispravnih postova u jednom upozorenju
+const ALERT_ISSUES_LIMIT: usize = 5;
+
+// kopije stranica koje nisu mogle da se procitaju
+pub const HTML_DIRECTORY: &str = "./logs/html";
+
+// stanje jednog izvora izmedju dva citanja
+#[derive(Debug, Default)]
+struct SourceHealth {
+    consecutive_failures: u32,
+    // broj postova pri poslednjem uspesnom citanju
+    last_post_count: Option<usize>,
+    // upozorenje je poslato, sledece tek posle oporavka
+    alerted: bool,
+    // upozorenje o neispravnim postovima je poslato
+    issues_alerted: bool
+}
+
+pub struct Alert {
+    pub source_name: String,
+    pub title: String,
+    pub description: String,
+    // selektor koji je najverovatnije zakazao
+    pub selector: Option<String>,
+    // sacuvana kopija stranice
+    pub html_path: Option<String>,
+    pub html: Option<String>
+}
+
+// prati uzastopne greske, citanja bez postova i nagle padove broja postova
+pub struct HealthMonitor {
+    // SIP_HEALTH_FAILURES
+    failure_threshold: u32,
+    // SIP_HEALTH_DROP_PERCENT
+    drop_percent: usize,
+    html_directory: String,
+    sources: HashMap<String, SourceHealth>,
+    alerts: Vec<Alert>
+}
+
+// cuva stranicu da bi mogla da se pregleda kasnije
+fn save_html(directory: &str, source: &dyn PostSource, html: &str) -> Option<String> {
+    if let Err(why) = std::fs::create_dir_all(directory) {
+        logger::log_sync("HLTH", format!("Can't create {}: {}", directory, why));
+        return None;
+    }
+
+    let path: String = format!("{}/{}_{}.html", directory, source.id(), Utc::now().format("%Y%m%d_%H%M%S"));
+    return match std::fs::write(&path, html) {
+        Ok(()) => Some(path),
+        Err(why) => {
+            logger::log_sync("HLTH", format!("Can't save {}: {}", path, why));
+            None
+        }
+    };
+}
+
+impl HealthMonitor {
+    pub fn new(failure_threshold: u32, drop_percent: usize, html_directory: &str) -> HealthMonitor {
+        return HealthMonitor {
+            failure_threshold,
+            drop_percent,
+            html_directory: html_directory.to_string(),
+            sources: HashMap::<String, SourceHealth>::new(),
+            alerts: Vec::<Alert>::new()
+        };
+    }
+
+    fn alert(&mut self, source: &dyn PostSource, title: &str, description: String, selector: Option<String>, html: Option<&str>) {
+        let health: &mut SourceHealth = self.sources.entry(source.id().to_string()).or_default();
+        if health.alerted {
+            return;
+        }
+        health.alerted = true;
+
+        logger::log_sync("HLTH", format!("[{}] {}: {}", source.id(), title, description));
+        self.alerts.push(Alert {
+            source_name: source.name().to_string(),
+            title: title.to_string(),
+            description,
+            selector,
+            html_path: html.and_then(|html| save_html(&self.html_directory, source, html)),
+            html: html.map(|html| html.to_string())
+        });
+    }
+
+    // greska pri preuzimanju ili parsiranju
+    // `html` je stranica koja nije mogla da se parsira, ako je preuzeta
+    pub fn record_failure(&mut self, source: &dyn PostSource, error: &SipError, html: Option<&str>) {
+        let health: &mut SourceHealth = self.sources.entry(source.id().to_string()).or_default();
+        health.consecutive_failures += 1;
+        let failures: u32 = health.consecutive_failures;
+
+        if failures >= self.failure_threshold {
+            self.alert(source, "Citanje ne uspeva", format!("{} uzastopnih gresaka, poslednja: {}", failures, error), source.selector_summary(), html);
+        }
+    }
+
+    // uspesno parsirana stranica sa `post_count` postova
+    pub fn record_posts(&mut self, source: &dyn PostSource, post_count: usize, html: &str) {
+        let health: &mut SourceHealth = self.sources.entry(source.id().to_string()).or_default();
+        let previous: Option<usize> = health.last_post_count;
+
+        if post_count == 0 {
+            health.consecutive_failures += 1;
+            self.alert(source, "Nema postova", "Stranica je procitana, ali nijedan post nije pronadjen".to_string(), source.selector_summary(), Some(html));
+            return;
+        }
+
+        // nagli pad u odnosu na prethodno citanje
+        let dropped: bool = previous.is_some_and(|previous| post_count * 100 < previous * (100 - self.drop_percent.min(100)));
+
+        let recovered: bool = health.alerted;
+        health.consecutive_failures = 0;
+        health.last_post_count = Some(post_count);
+        health.alerted = false;
+
+        if recovered {
+            logger::log_sync("HLTH", format!("[{}] Recovered with {} posts", source.id(), post_count));
+            self.alerts.push(Alert {
+                source_name: source.name().to_string(),
+                title: "Citanje ponovo radi".to_string(),
+                description: format!("Procitano {} postova", post_count),
+                selector: None,
+                html_path: None,
+                html: None
+            });
+        }
+
+        // pad se prijavljuje jednom, novi broj postova postaje osnova za poredjenje
+        if dropped {
+            self.alert(source, "Nagli pad broja postova", format!("Broj postova je pao sa {} na {}", previous.unwrap_or_default(), post_count), source.selector_summary(), Some(html));
+            if let Some(health) = self.sources.get_mut(source.id()) {
+                health.alerted = false;
+            }
+        }
+    }
+
+    // preskoceni postovi, upozorenje se salje jednom dok se stranica ne procita bez njih
+    pub fn record_issues(&mut self, source: &dyn PostSource, issues: &[ParseIssue], html: &str) {
+        let health: &mut SourceHealth = self.sources.entry(source.id().to_string()).or_default();
+        if issues.is_empty() {
+            health.issues_alerted = false;
+            return;
+        }
+        if health.issues_alerted {
+            return;
+        }
+        health.issues_alerted = true;
+
+        let mut description: String = format!("Preskoceno {} neispravnih postova, ostali postovi su poslati", issues.len());
+        for issue in issues.iter().take(ALERT_ISSUES_LIMIT) {
+            description.push_str(&format!("\n\n**{}**\n`{}`", issue.reason, issue.snippet.replace('`', "'")));
+        }
+
+        logger::log_sync("HLTH", format!("[{}] {} malformed posts", source.id(), issues.len()));
+        self.alerts.push(Alert {
+            source_name: source.name().to_string(),
+            title: "Neispravni postovi".to_string(),
+            description,
+            selector: source.selector_summary(),
+            html_path: save_html(&self.html_directory, source, html),
+            html: Some(html.to_string())
+        });
+    }
+
+    // obavestenje vlasniku koje nije vezano za rad citaca
+    pub fn notify(&mut self, source: &dyn PostSource, title: &str, description: String) {
+        self.alerts.push(Alert {
+            source_name: source.name().to_string(),
+            title: title.to_string(),
+            description,
+            selector: None,
+            html_path: None,
+            html: None
+        });
+    }
+
+    pub fn take_alerts(&mut self) -> Vec<Alert> {
+        return std::mem::take(&mut self.alerts);
+    }
+}
+
+// salje upozorenje u SIP_ALERT_CHANNEL, a ako kanal nije podesen vlasniku bota
+pub async fn send_alert(http: &Http, owner_id: UserId, alert: &Alert) -> Result<(), DiscordError> {
+    let channel_id: ChannelId = match CONFIG.alert_channel {
+        Some(channel_id) => ChannelId(channel_id),
+        None => owner_id.create_dm_channel(http).map_err(|err| DiscordError::DiscordMessageError(err.to_string())).await?.id
+    };
+
+    let mut description: String = alert.description.clone();
+    if let Some(selector) = &alert.selector {
+        description.push_str(&format!("\nSelektori: {}", selector));
+    }
+    if let Some(html_path) = &alert.html_path {
+        description.push_str(&format!("\nKopija stranice: `{}`", html_path));
+    }
+
+    let files: Vec<AttachmentType> = match &alert.html {
+        Some(html) if html.len() as u64 <= DISCORD_UPLOAD_LIMIT => vec![AttachmentType::Bytes { data: Cow::Borrowed(html.as_bytes()), filename: "stranica.html".to_string() }],
+        _ => Vec::<AttachmentType>::new()
+    };
+
+    channel_id.send_files(http, files, |m|
+        m
+            .embed(|e|
+                e
+                    .author(|a| a.name(&alert.source_name))
+                    .thumbnail("https://i.imgur.com/dyu12dZ.png")
+                    .title(format!(":warning: {} :warning:", alert.title))
+                    .description(description)
+                    .color(0x65BD36)
+            )
+    ).map_err(|err| DiscordError::DiscordMessageError(err.to_string())).await?;
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::fetcher::SipPostType;
+    use crate::sources::SipSource;
+
+    fn monitor(directory: &TempDir) -> HealthMonitor {
+        return HealthMonitor::new(3, 50, &directory.path().display().to_string());
+    }
+
+    fn titles(monitor: &mut HealthMonitor) -> Vec<String> {
+        return monitor.take_alerts().into_iter().map(|alert| alert.title).collect();
+    }
+
+    fn failure() -> SipError {
+        return SipError::PostError("timeout".to_string());
+    }
+
+    fn issue() -> ParseIssue {
+        return ParseIssue { post_type: SipPostType::New, reason: "Error parsing title".to_string(), snippet: "<li>`x`</li>".to_string() };
+    }
+
+    #[test]
+    fn alert_after_failures_in_a_row() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let mut monitor: HealthMonitor = monitor(&directory);
+
+        monitor.record_failure(&SipSource, &failure(), None);
+        monitor.record_failure(&SipSource, &failure(), None);
+        assert!(titles(&mut monitor).is_empty());
+
+        // uspesno citanje prekida niz
+        monitor.record_posts(&SipSource, 10, "<html></html>");
+        monitor.record_failure(&SipSource, &failure(), None);
+        monitor.record_failure(&SipSource, &failure(), None);
+        assert!(titles(&mut monitor).is_empty());
+
+        monitor.record_failure(&SipSource, &failure(), Some("<html>greska</html>"));
+        let alerts: Vec<Alert> = monitor.take_alerts();
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].description.starts_with("3 uzastopnih gresaka"));
+        let html_path: String = alerts[0].html_path.clone().unwrap();
+        assert!(html_path.starts_with(&directory.path().display().to_string()));
+        assert_eq!(std::fs::read_to_string(html_path).unwrap(), "<html>greska</html>");
+    }
+
+    #[test]
+    fn alert_is_sent_once_until_recovery() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let mut monitor: HealthMonitor = monitor(&directory);
+
+        for _ in 0..10 {
+            monitor.record_failure(&SipSource, &failure(), None);
+        }
+        assert_eq!(titles(&mut monitor), vec!["Citanje ne uspeva"]);
+
+        monitor.record_posts(&SipSource, 10, "<html></html>");
+        assert_eq!(titles(&mut monitor), vec!["Citanje ponovo radi"]);
+
+        // posle oporavka upozorenje moze ponovo da se posalje
+        for _ in 0..3 {
+            monitor.record_failure(&SipSource, &failure(), None);
+        }
+        assert_eq!(titles(&mut monitor), vec!["Citanje ne uspeva"]);
+    }
+
+    #[test]
+    fn page_without_posts_alerts() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let mut monitor: HealthMonitor = monitor(&directory);
+
+        monitor.record_posts(&SipSource, 0, "<html></html>");
+        monitor.record_posts(&SipSource, 0, "<html></html>");
+
+        let alerts: Vec<Alert> = monitor.take_alerts();
+        assert_eq!(alerts.iter().map(|alert| alert.title.as_str()).collect::<Vec<&str>>(), vec!["Nema postova"]);
+        assert!(alerts[0].selector.is_some());
+    }
+
+    #[test]
+    fn sudden_drop_alerts_once() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let mut monitor: HealthMonitor = monitor(&directory);
+
+        monitor.record_posts(&SipSource, 20, "<html></html>");
+        // pad od tacno 50% nije nagao
+        monitor.record_posts(&SipSource, 10, "<html></html>");
+        assert!(titles(&mut monitor).is_empty());
+
+        monitor.record_posts(&SipSource, 4, "<html></html>");
+        assert_eq!(titles(&mut monitor), vec!["Nagli pad broja postova"]);
+
+        // novi broj postova je osnova za poredjenje, bez poruke o oporavku
+        monitor.record_posts(&SipSource, 4, "<html></html>");
+        assert!(titles(&mut monitor).is_empty());
+    }
+
+    #[test]
+    fn drop_threshold_is_configurable() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let mut monitor: HealthMonitor = HealthMonitor::new(3, 10, &directory.path().display().to_string());
+
+        monitor.record_posts(&SipSource, 20, "<html></html>");
+        monitor.record_posts(&SipSource, 17, "<html></html>");
+        assert_eq!(titles(&mut monitor), vec!["Nagli pad broja postova"]);
+
+        // 100% se prijavljuje samo kao stranica bez postova
+        let mut monitor: HealthMonitor = HealthMonitor::new(3, 100, &directory.path().display().to_string());
+
+        monitor.record_posts(&SipSource, 20, "<html></html>");
+        monitor.record_posts(&SipSource, 1, "<html></html>");
+
+        assert!(titles(&mut monitor).is_empty());
+    }
+
+    #[test]
+    fn malformed_posts_alert_once() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let mut monitor: HealthMonitor = monitor(&directory);
+
+        monitor.record_issues(&SipSource, &[issue(), issue()], "<html></html>");
+        monitor.record_issues(&SipSource, &[issue()], "<html></html>");
+        let alerts: Vec<Alert> = monitor.take_alerts();
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].description.starts_with("Preskoceno 2 neispravnih postova"));
+        assert!(alerts[0].description.contains("`<li>'x'</li>`"));
+
+        // stranica bez neispravnih postova vraca upozorenje u pocetno stanje
+        monitor.record_issues(&SipSource, &[], "<html></html>");
+        monitor.record_issues(&SipSource, &[issue()], "<html></html>");
+        assert_eq!(titles(&mut monitor), vec!["Neispravni postovi"]);
+    }
+}
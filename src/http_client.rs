@@ -0,0 +1,193 @@
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Certificate, Client, Proxy, RequestBuilder, Response, StatusCode};
+
+use crate::config::CONFIG;
+use crate::errors::{FetchErrorKind, SipError};
+use crate::logger;
+use crate::sources::{FetchOutcome, Validators};
+
+static HTTP_CLIENT: OnceLock<Client> = OnceLock::new();
+
+fn build_client() -> Result<Client, SipError> {
+    let mut builder = Client::builder()
+        .connect_timeout(Duration::from_secs(CONFIG.http_connect_timeout))
+        .timeout(Duration::from_secs(CONFIG.http_timeout))
+        .user_agent(CONFIG.http_user_agent.clone())
+        .gzip(true)
+        .brotli(true);
+
+    // http://, https:// ili socks5:// proxy
+    if let Some(proxy_url) = &CONFIG.http_proxy {
+        let proxy: Proxy = Proxy::all(proxy_url).map_err(|err| SipError::ConfigError(format!("Invalid SIP_HTTP_PROXY: {}", err)))?;
+        builder = builder.proxy(proxy);
+    }
+
+    // dodatni sertifikat (PEM), npr. za fakultetski proxy
+    if let Some(ca_path) = &CONFIG.http_ca_cert {
+        let pem: Vec<u8> = std::fs::read(ca_path).map_err(|err| SipError::ConfigError(format!("Can't read SIP_HTTP_CA_CERT: {}", err)))?;
+        let certificate: Certificate = Certificate::from_pem(&pem).map_err(|err| SipError::ConfigError(format!("Invalid SIP_HTTP_CA_CERT: {}", err)))?;
+        builder = builder.add_root_certificate(certificate);
+    }
+
+    return builder.build().map_err(|err| SipError::ConfigError(err.to_string()));
+}
+
+// pravi zajednicki klijent pri pokretanju, da bi greske u podesavanjima bile odmah vidljive
+pub fn setup_http_client() -> Result<(), SipError> {
+    let client: Client = build_client()?;
+    let _ = HTTP_CLIENT.set(client);
+    return Ok(());
+}
+
+pub fn client() -> &'static Client {
+    return HTTP_CLIENT.get_or_init(|| build_client().unwrap_or_default());
+}
+
+pub fn fetch_error(err: reqwest::Error) -> SipError {
+    let kind: FetchErrorKind = if err.is_timeout() {
+        FetchErrorKind::Timeout
+    } else if err.is_connect() {
+        FetchErrorKind::Connect
+    } else if let Some(status) = err.status() {
+        FetchErrorKind::Status(status.as_u16())
+    } else if err.is_body() || err.is_decode() {
+        FetchErrorKind::Body
+    } else {
+        FetchErrorKind::Request
+    };
+    return SipError::FetchError(kind, err.to_string());
+}
+
+fn is_retryable(error: &SipError) -> bool {
+    return match error {
+        SipError::FetchError(kind, _) => kind.is_retryable(),
+        _ => false
+    };
+}
+
+// greska pri citanju tela odgovora, npr. prekinuta veza usred prenosa
+pub fn body_error(err: reqwest::Error) -> SipError {
+    return SipError::FetchError(FetchErrorKind::Body, err.to_string());
+}
+
+// salje zahtev i vraca uspesan odgovor
+pub async fn send(request: RequestBuilder) -> Result<Response, SipError> {
+    return match request.send().await {
+        Ok(response) => response.error_for_status().map_err(fetch_error),
+        Err(err) => Err(fetch_error(err))
+    };
+}
+
+// prolazne greske se ponavljaju `retries` puta, sa eksponencijalnim cekanjem od `retry_delay` milisekundi
+async fn retry<T, F: Fn() -> Fut, Fut: Future<Output = Result<T, SipError>>>(retries: u32, retry_delay: u64, operation: F) -> Result<T, SipError> {
+    let mut attempt: u32 = 0;
+
+    loop {
+        match operation().await {
+            Err(why) if is_retryable(&why) && attempt < retries => {
+                let delay: u64 = retry_delay.saturating_mul(1 << attempt.min(16));
+                logger::log("FETCH", format!("{} - retry {}/{} in {} ms", why, attempt + 1, retries, delay)).await;
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                attempt += 1;
+            },
+            result => return result
+        };
+    }
+}
+
+// zahtev i citanje odgovora se ponavljaju zajedno, pa se ponavlja i prekinuto citanje tela
+pub async fn with_retries<T, F: Fn() -> Fut, Fut: Future<Output = Result<T, SipError>>>(operation: F) -> Result<T, SipError> {
+    return retry(CONFIG.http_retries, CONFIG.http_retry_delay, operation).await;
+}
+
+pub async fn get_text(url: &str) -> Result<String, SipError> {
+    return with_retries(|| async {
+        let response: Response = send(client().get(url)).await?;
+        return response.text().await.map_err(body_error);
+    }).await;
+}
+
+// uslovni zahtev, server vraca 304 ako se stranica nije promenila od poslednjeg citanja
+pub async fn get_text_conditional(url: &str, validators: &Validators) -> Result<FetchOutcome, SipError> {
+    return with_retries(|| get_text_if_modified(url, validators)).await;
+}
+
+async fn get_text_if_modified(url: &str, validators: &Validators) -> Result<FetchOutcome, SipError> {
+    let mut request: RequestBuilder = client().get(url);
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    let response: Response = send(request).await?;
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(FetchOutcome::NotModified);
+    }
+
+    let header_value = |name| response.headers().get(name).and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok()).map(|value| value.to_string());
+    let new_validators: Validators = Validators {
+        etag: header_value(ETAG),
+        last_modified: header_value(LAST_MODIFIED),
+        body_hash: None,
+        parser_hash: None
+    };
+
+    let body: String = response.text().await.map_err(body_error)?;
+    return Ok(FetchOutcome::Modified { body, validators: new_validators });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    // vraca zadate greske redom, zatim uspeh
+    async fn attempts(errors: Vec<SipError>) -> (Result<u32, SipError>, u32) {
+        let calls: AtomicU32 = AtomicU32::new(0);
+        let errors: std::sync::Mutex<Vec<SipError>> = std::sync::Mutex::new(errors);
+        let result: Result<u32, SipError> = retry(2, 0, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let mut errors = errors.lock().unwrap();
+            if errors.is_empty() {
+                return Ok(calls.load(Ordering::SeqCst));
+            }
+            return Err(errors.remove(0));
+        }).await;
+        return (result, calls.load(Ordering::SeqCst));
+    }
+
+    fn error(kind: FetchErrorKind) -> SipError {
+        return SipError::FetchError(kind, kind.to_string());
+    }
+
+    #[tokio::test]
+    async fn failed_body_read_is_retried() {
+        let (result, calls) = attempts(vec![error(FetchErrorKind::Body), error(FetchErrorKind::Timeout)]).await;
+
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn retries_are_limited() {
+        let (result, calls) = attempts(vec![error(FetchErrorKind::Body), error(FetchErrorKind::Body), error(FetchErrorKind::Status(503))]).await;
+
+        assert!(matches!(result, Err(SipError::FetchError(FetchErrorKind::Status(503), _))));
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (result, calls) = attempts(vec![error(FetchErrorKind::Status(404))]).await;
+
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
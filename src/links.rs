@@ -0,0 +1,179 @@
+use reqwest::Url;
+
+// parametri koji ne menjaju sadrzaj stranice, vec samo prate odakle je posetilac dosao
+const TRACKING_PARAMETERS: [&str; 11] = ["fbclid", "gclid", "dclid", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid", "_ga", "ref", "ref_src"];
+
+// parametri u kojima sip i slicni sajtovi cuvaju id posta
+const ID_PARAMETERS: [&str; 8] = ["id", "p", "post", "post_id", "news", "news_id", "vest", "article"];
+
+fn is_tracking_parameter(name: &str) -> bool {
+    let name: String = name.to_lowercase();
+    return name.starts_with("utm_") || TRACKING_PARAMETERS.contains(&name.as_str());
+}
+
+// apsolutni link bez fragmenta i parametara za pracenje
+// sema, port i putanja ostaju kakvi jesu, pa link i dalje radi na izvoru koji nema https
+// link koji ne moze da se procita ostaje kakav jeste
+pub fn resolve_link(base_url: Option<&Url>, href: &str) -> String {
+    return match parse_link(base_url, href) {
+        Some(url) => url.to_string(),
+        None => href.trim().to_string()
+    };
+}
+
+fn parse_link(base_url: Option<&Url>, href: &str) -> Option<Url> {
+    let href: &str = href.trim();
+    let parsed: Option<Url> = match base_url {
+        Some(base_url) => base_url.join(href).ok(),
+        None => Url::parse(href).ok()
+    };
+    let mut url: Url = match parsed {
+        Some(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+        _ => return None
+    };
+
+    url.set_fragment(None);
+
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| !is_tracking_parameter(name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+
+    return Some(url);
+}
+
+// link u jedinstvenom obliku, samo za poredjenje postova
+// https umesto http, bez podrazumevanog porta i kose crte na kraju
+fn canonicalize_link(base_url: Option<&Url>, href: &str) -> String {
+    let mut url: Url = match parse_link(base_url, href) {
+        Some(url) => url,
+        None => return href.trim().to_string()
+    };
+
+    if url.scheme() == "http" {
+        let _ = url.set_scheme("https");
+    }
+    // podrazumevani port posle promene seme
+    if url.port() == Some(80) || url.port() == Some(443) {
+        let _ = url.set_port(None);
+    }
+
+    let path: String = url.path().to_string();
+    if path.len() > 1 && path.ends_with('/') {
+        url.set_path(path.trim_end_matches('/'));
+    }
+
+    return url.to_string();
+}
+
+// id posta iz linka i deo linka ispred njega
+// iz parametra (?id=123 daje "/?id") ili poslednjeg dela putanje (/vest/123-naslov daje "/vest")
+// fajlovi (/files/15.pdf) nemaju id
+fn post_id(url: &Url) -> Option<(String, String)> {
+    for (name, value) in url.query_pairs() {
+        let name: String = name.to_lowercase();
+        if ID_PARAMETERS.contains(&name.as_str()) && !value.is_empty() && value.chars().all(|character| character.is_ascii_digit()) {
+            return Some((format!("{}?{}", url.path(), name), value.into_owned()));
+        }
+    }
+
+    let segments: Vec<&str> = url.path_segments()?.filter(|segment| !segment.is_empty()).collect();
+    let (last_segment, parents): (&&str, &[&str]) = segments.split_last()?;
+    let digits: String = last_segment.chars().take_while(|character| character.is_ascii_digit()).collect();
+    let rest: &str = &last_segment[digits.len()..];
+    if !digits.is_empty() && (rest.is_empty() || rest.starts_with('-')) {
+        return Some((format!("/{}", parents.join("/")), digits));
+    }
+
+    return None;
+}
+
+// kljuc po kom se post prepoznaje izmedju dva citanja
+// "host/putanja:id" ako link sadrzi id posta, inace ceo kanonski link
+// putanja ostaje u kljucu, jer isti broj na razlicitim stranicama nije isti post
+pub fn post_key(link: &str) -> String {
+    let canonical_link: String = canonicalize_link(None, link);
+    let url: Url = match Url::parse(&canonical_link) {
+        Ok(url) => url,
+        Err(_) => return canonical_link
+    };
+    return match (url.host_str(), post_id(&url)) {
+        (Some(host), Some((prefix, id))) => format!("{}{}:{}", host, prefix, id),
+        _ => canonical_link
+    };
+}
+
+fn is_post_key(value: &str) -> bool {
+    if value.contains("://") {
+        return false;
+    }
+    return match value.rsplit_once(':') {
+        Some((prefix, id)) => {
+            let host: &str = prefix.split('/').next().unwrap_or_default();
+            prefix.contains('/') && host.contains('.') && !id.is_empty() && id.chars().all(|character| character.is_ascii_digit())
+        },
+        None => false
+    };
+}
+
+// kljuc za vrednost iz baze, stare verzije su cuvale link umesto kljuca
+// kljucevi koji su vec u novom obliku se ne menjaju
+pub fn stored_post_key(base_url: Option<&Url>, stored: &str) -> String {
+    if is_post_key(stored) || stored.is_empty() {
+        return stored.to_string();
+    }
+    return post_key(&resolve_link(base_url, stored));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sip() -> Url {
+        return Url::parse("http://sip.elfak.ni.ac.rs/").unwrap();
+    }
+
+    #[test]
+    fn resolve_link_keeps_scheme_and_strips_tracking() {
+        assert_eq!(resolve_link(Some(&sip()), "/vesti/15?utm_source=fb&page=2#komentari"), "http://sip.elfak.ni.ac.rs/vesti/15?page=2");
+        assert_eq!(resolve_link(Some(&sip()), " https://elfak.ni.ac.rs/a/?fbclid=x "), "https://elfak.ni.ac.rs/a/");
+        assert_eq!(resolve_link(Some(&sip()), "mailto:sip@elfak.ni.ac.rs"), "mailto:sip@elfak.ni.ac.rs");
+    }
+
+    #[test]
+    fn canonicalize_link_normalizes_scheme_port_and_slash() {
+        assert_eq!(canonicalize_link(None, "http://sip.elfak.ni.ac.rs:80/vesti/15/"), "https://sip.elfak.ni.ac.rs/vesti/15");
+        assert_eq!(canonicalize_link(None, "https://sip.elfak.ni.ac.rs:443/?ref=x"), "https://sip.elfak.ni.ac.rs/");
+        assert_eq!(canonicalize_link(None, "http://sip.elfak.ni.ac.rs:8080/a"), "https://sip.elfak.ni.ac.rs:8080/a");
+        assert_eq!(canonicalize_link(Some(&sip()), "vesti/15#top"), "https://sip.elfak.ni.ac.rs/vesti/15");
+        assert_eq!(canonicalize_link(None, "nije link"), "nije link");
+    }
+
+    #[test]
+    fn post_key_keeps_path_prefix() {
+        assert_eq!(post_key("http://sip.elfak.ni.ac.rs/vesti/15-novi-raspored"), "sip.elfak.ni.ac.rs/vesti:15");
+        assert_eq!(post_key("https://sip.elfak.ni.ac.rs/vesti/15/"), "sip.elfak.ni.ac.rs/vesti:15");
+        assert_eq!(post_key("https://sip.elfak.ni.ac.rs/?id=15&utm_medium=x"), "sip.elfak.ni.ac.rs/?id:15");
+        assert_ne!(post_key("https://sip.elfak.ni.ac.rs/vesti/15"), post_key("https://sip.elfak.ni.ac.rs/ispiti/15"));
+        assert_ne!(post_key("https://sip.elfak.ni.ac.rs/?id=15"), post_key("https://sip.elfak.ni.ac.rs/?p=15"));
+    }
+
+    #[test]
+    fn files_are_not_post_ids() {
+        assert_eq!(post_key("https://sip.elfak.ni.ac.rs/files/15.pdf"), "https://sip.elfak.ni.ac.rs/files/15.pdf");
+        assert_ne!(post_key("https://sip.elfak.ni.ac.rs/files/15.pdf"), post_key("https://sip.elfak.ni.ac.rs/files/15"));
+    }
+
+    #[test]
+    fn stored_post_key_keeps_new_keys() {
+        assert_eq!(stored_post_key(Some(&sip()), "sip.elfak.ni.ac.rs/vesti:15"), "sip.elfak.ni.ac.rs/vesti:15");
+        assert_eq!(stored_post_key(Some(&sip()), "/vesti/15"), "sip.elfak.ni.ac.rs/vesti:15");
+        assert_eq!(stored_post_key(Some(&sip()), "https://sip.elfak.ni.ac.rs/files/15.pdf"), "https://sip.elfak.ni.ac.rs/files/15.pdf");
+    }
+}
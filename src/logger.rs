@@ -5,7 +5,7 @@ use chrono_tz::Tz;
 use tokio::{fs::OpenOptions, io::AsyncWriteExt};
 
 lazy_static::lazy_static! {
-    static ref TIMEZONE: Tz = "Europe/Belgrade".parse().unwrap();
+    pub static ref TIMEZONE: Tz = "Europe/Belgrade".parse().unwrap();
 }
 
 pub fn current_date_time() -> (String, String) {
@@ -27,7 +27,6 @@ pub async fn log<T: Into<String>>(log_type: &str, raw_message: T) {
 
     let file = OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open(format!("./logs/{}.txt", locale_date))
         .await;
@@ -50,7 +49,6 @@ pub fn log_sync<T: Into<String>>(log_type: &str, raw_message: T) {
 
     let file = std::fs::OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open(format!("./logs/{}.txt", locale_date));
     if let Ok(mut writeable_file) = file {
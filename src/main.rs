@@ -1,50 +1,78 @@
+#![allow(clippy::needless_return, clippy::enum_variant_names, clippy::iter_nth_zero)]
+
 use std::collections::HashSet;
+use std::sync::Arc;
 
 use errors::DiscordError;
-use pickledb::PickleDb;
 
 use serenity::Client;
 use serenity::http::Http;
 use serenity::futures::TryFutureExt;
-use serenity::model::prelude::{CurrentApplicationInfo, UserId, Ready};
+use serenity::model::prelude::{ChannelId, CurrentApplicationInfo, GuildId, UserId, Ready};
 use serenity::framework::StandardFramework;
 use serenity::framework::standard::macros::group;
 use serenity::prelude::{GatewayIntents, Context, EventHandler};
 
+mod archive;
+mod config;
+mod database;
+mod dates;
+mod delivery;
 mod fetcher;
+mod flood;
+mod health;
+mod http_client;
 mod storage;
 mod errors;
+mod links;
 mod logger;
+mod markdown;
+mod repository;
 mod commands;
+mod schedule;
+mod selectors;
+mod sources;
+mod webhooks;
+#[cfg(test)]
+mod test_fixtures;
 use commands::*;
 
-use dotenvy;
-
-use tokio::sync::Mutex;
-
+use crate::config::CONFIG;
+use crate::delivery::delivery_main;
 use crate::fetcher::fetcher_main;
-
-const FILE: &str = "storage.json";
-
-lazy_static::lazy_static! {
-    // napravi bazu, ako ne postoji
-    static ref DATABASE: Mutex<PickleDb> = Mutex::<PickleDb>::new(
-        match PickleDb::load(FILE, pickledb::PickleDbDumpPolicy::AutoDump, pickledb::SerializationMethod::Json) {
-            Err(_) => PickleDb::new(FILE, pickledb::PickleDbDumpPolicy::AutoDump, pickledb::SerializationMethod::Json),
-            Ok(database) => database
-        }
-    );
-}
+use crate::repository::{RepositoryKey, SharedRepository};
+use crate::schedule::PollSchedule;
+use crate::sources::PostSource;
 
 #[group]
-#[commands(sip)]
+#[commands(sip, sipdatum, sipuloga, sipodobri, sipodbaci)]
 struct General;
 
-struct Handler;
+struct Handler {
+    owner_id: UserId,
+    repository: SharedRepository
+}
+
+impl Handler {
+    async fn reconcile(&self, context: &Context, guild_ids: &[GuildId], complete: bool) {
+        let bot_id: UserId = context.cache.current_user_id();
+        match webhooks::reconcile_webhooks(&context.http, &self.repository, bot_id, guild_ids, complete).await {
+            Ok(report) => {
+                logger::log("HOOKS", format!("Reconciled: {} refreshed, {} discovered, {} pruned", report.refreshed, report.discovered, report.pruned.len())).await;
+                for pruned in report.pruned.iter() {
+                    if let Err(why) = webhooks::notify_owner_about_pruned(&context.http, self.owner_id, pruned).await {
+                        logger::log("ERR", why.to_string()).await;
+                    }
+                }
+            },
+            Err(why) => logger::log("ERR", why.to_string()).await
+        };
+    }
+}
 
 #[serenity::async_trait]
 impl EventHandler for Handler {
-    async fn ready(&self, context: Context, _: Ready) {
+    async fn ready(&self, context: Context, ready: Ready) {
         use serenity::model::gateway::Activity;
         use serenity::model::user::OnlineStatus;
 
@@ -54,6 +82,14 @@ impl EventHandler for Handler {
         context.set_presence(Some(activity), status).await;
 
         logger::log("READY", "SipBot is ready").await;
+
+        // uskladjivanje baze sa webhook-ovima na svim serverima
+        let guild_ids: Vec<GuildId> = ready.guilds.iter().map(|guild| guild.id).collect();
+        self.reconcile(&context, &guild_ids, true).await;
+    }
+
+    async fn webhook_update(&self, context: Context, guild_id: GuildId, _: ChannelId) {
+        self.reconcile(&context, &[guild_id], false).await;
     }
 
 }
@@ -62,20 +98,29 @@ impl EventHandler for Handler {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     dotenvy::dotenv().expect("Missing .env file");
+    config::setup_config()?;
     let token: String = std::env::var("TOKEN").expect("Missing TOKEN");
-    let prefix: String = std::env::var("PREFIX").expect("Missing PREFIX");
+    let prefix: String = CONFIG.prefix.clone();
+
+    http_client::setup_http_client()?;
+    let schedule: PollSchedule = PollSchedule::from_config()?;
+
+    let sources: Vec<Box<dyn PostSource>> = sources::all_sources();
 
+    let repository: SharedRepository = repository::shared(storage::open_repository(&sources)?);
     {
-        let mut database = DATABASE.lock().await;
-        storage::setup_storage(&mut database)?;
+        let mut database = repository.lock().await;
+        storage::setup_storage(&mut **database, &sources)?;
     }
+    selectors::setup_selectors()?;
 
     // discord bot
     let http: Http = Http::new(&token);
 
     let application_info: CurrentApplicationInfo = http.get_current_application_info().map_err(|err| DiscordError::DiscordAppInfoError(err.to_string())).await?;
+    let owner_id: UserId = application_info.owner.id;
     let mut owners: HashSet<UserId> = HashSet::<UserId>::new();
-    owners.insert(application_info.owner.id);
+    owners.insert(owner_id);
 
     let framework = StandardFramework::new().configure(|c|
         c
@@ -89,20 +134,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let intents: GatewayIntents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::GUILD_WEBHOOKS | GatewayIntents::MESSAGE_CONTENT;
     let mut client: Client = Client::builder(&token, intents)
-        .event_handler(Handler)
+        .event_handler(Handler { owner_id, repository: repository.clone() })
         .framework(framework)
         .map_err(|err| DiscordError::DiscordBuilderError(err.to_string()))
         .await?;
+    client.data.write().await.insert::<RepositoryKey>(repository.clone());
 
-    let http_fetcher: Http = Http::new(&token);
+    let http_delivery: Arc<Http> = Arc::new(Http::new(&token));
+    let http_fetcher: Arc<Http> = http_delivery.clone();
+    let repository_fetcher: SharedRepository = repository.clone();
 
 
-    // pokrecemo dva nezavisna zadatka
-    // discord bot i sip fetcher
-    let (bot_handle, fetcher_handle) = tokio::join!(client.start(), tokio::task::spawn(async {
-        fetcher_main(http_fetcher).await;
+    // pokrecemo tri nezavisna zadatka
+    // discord bot, sip fetcher i slanje poruka
+    let (bot_handle, fetcher_handle, delivery_handle) = tokio::join!(client.start(), tokio::task::spawn(async move {
+        fetcher_main(sources, schedule, http_fetcher, owner_id, repository_fetcher).await;
+    }), tokio::task::spawn(async move {
+        delivery_main(http_delivery, owner_id, repository).await;
     }));
 
-    dbg!(&bot_handle, &fetcher_handle);
+    dbg!(&bot_handle, &fetcher_handle, &delivery_handle);
     return Ok(());
 }
@@ -0,0 +1,318 @@
+use ego_tree::NodeRef;
+use reqwest::Url;
+use scraper::{ElementRef, Node};
+
+// pretvaranje html-a posta u discord markdown
+// podrzano: podebljano, iskoseno, podvuceno, precrtano, kod, linkovi, liste i novi redovi
+
+// elementi posle kojih tekst ide u novi pasus
+const BLOCK_ELEMENTS: [&str; 16] = ["p", "div", "section", "article", "main", "header", "footer", "blockquote", "table", "tr", "h1", "h2", "h3", "h4", "h5", "h6"];
+
+// elementi koji se ne prikazuju
+const SKIPPED_ELEMENTS: [&str; 6] = ["script", "style", "noscript", "img", "iframe", "head"];
+
+struct Converter<'a> {
+    base_url: Option<&'a Url>,
+    // dubina ugnjezdenih lista
+    list_depth: usize
+}
+
+// discord markdown znakovi u obicnom tekstu
+fn escape_markdown(text: &str) -> String {
+    let mut escaped: String = String::with_capacity(text.len());
+    for character in text.chars() {
+        if matches!(character, '\\' | '*' | '_' | '~' | '`' | '|') {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    return escaped;
+}
+
+// razmaci i novi redovi iz html-a se svode na jedan razmak
+fn push_text(output: &mut String, text: &str) {
+    let mut collapsed: String = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if collapsed.is_empty() {
+        if !text.is_empty() && !output.is_empty() && !output.ends_with([' ', '\n']) {
+            output.push(' ');
+        }
+        return;
+    }
+
+    if text.starts_with(char::is_whitespace) && !output.is_empty() && !output.ends_with([' ', '\n']) {
+        output.push(' ');
+    }
+    if text.ends_with(char::is_whitespace) {
+        collapsed.push(' ');
+    }
+    output.push_str(&escape_markdown(&collapsed));
+}
+
+fn push_line_break(output: &mut String) {
+    while output.ends_with(' ') {
+        output.pop();
+    }
+    output.push('\n');
+}
+
+fn push_paragraph_break(output: &mut String) {
+    while output.ends_with(' ') {
+        output.pop();
+    }
+    if output.is_empty() || output.ends_with("\n\n") {
+        return;
+    }
+    output.push_str(if output.ends_with('\n') { "\n" } else { "\n\n" });
+}
+
+// oznaka se stavlja uz tekst, jer discord ne prikazuje `** tekst**`
+fn push_wrapped(output: &mut String, inner: &str, marker: &str) {
+    let trimmed: &str = inner.trim();
+    if trimmed.is_empty() {
+        output.push_str(inner);
+        return;
+    }
+    if inner.starts_with(char::is_whitespace) && !output.ends_with([' ', '\n']) && !output.is_empty() {
+        output.push(' ');
+    }
+    output.push_str(marker);
+    output.push_str(trimmed);
+    output.push_str(marker);
+    if inner.ends_with(char::is_whitespace) {
+        output.push(' ');
+    }
+}
+
+impl<'a> Converter<'a> {
+    fn render_children(&mut self, node: NodeRef<Node>, output: &mut String) {
+        for child in node.children() {
+            self.render_node(child, output);
+        }
+    }
+
+    fn render_inner(&mut self, node: NodeRef<Node>) -> String {
+        let mut inner: String = String::new();
+        self.render_children(node, &mut inner);
+        return inner;
+    }
+
+    fn render_link(&mut self, element: ElementRef, output: &mut String) {
+        let text: String = self.render_inner(*element);
+        let href: Option<Url> = element
+            .value()
+            .attr("href")
+            .map(|href| href.trim())
+            .filter(|href| !href.is_empty() && !href.starts_with('#') && !href.starts_with("javascript:"))
+            .and_then(|href| match self.base_url {
+                Some(base_url) => base_url.join(href).ok(),
+                None => Url::parse(href).ok()
+            });
+
+        let url: Url = match href {
+            Some(url) => url,
+            None => {
+                output.push_str(&text);
+                return;
+            }
+        };
+
+        let label: &str = text.trim();
+        if label.is_empty() || label == escape_markdown(url.as_str()) {
+            output.push_str(url.as_str());
+            return;
+        }
+
+        if text.starts_with(char::is_whitespace) && !output.is_empty() && !output.ends_with([' ', '\n']) {
+            output.push(' ');
+        }
+        // zagrade u linku bi zatvorile markdown link
+        output.push_str(&format!("[{}]({})", label.replace(['[', ']'], ""), url.as_str().replace('(', "%28").replace(')', "%29")));
+        if text.ends_with(char::is_whitespace) {
+            output.push(' ');
+        }
+    }
+
+    fn render_list(&mut self, element: ElementRef, ordered: bool, output: &mut String) {
+        push_line_break_if_needed(output);
+        self.list_depth += 1;
+
+        let start: usize = element.value().attr("start").and_then(|start| start.parse::<usize>().ok()).unwrap_or(1);
+        let items = element.children().filter_map(ElementRef::wrap).filter(|child| child.value().name() == "li");
+        for (number, item) in (start..).zip(items) {
+            let indent: String = "  ".repeat(self.list_depth - 1);
+            let bullet: String = if ordered { format!("{}. ", number) } else { "- ".to_string() };
+
+            let inner: String = self.render_inner(*item);
+            push_line_break_if_needed(output);
+            output.push_str(&indent);
+            output.push_str(&bullet);
+            output.push_str(inner.trim());
+            push_line_break(output);
+        }
+
+        self.list_depth -= 1;
+        if self.list_depth == 0 {
+            push_paragraph_break(output);
+        }
+    }
+
+    fn render_node(&mut self, node: NodeRef<Node>, output: &mut String) {
+        match node.value() {
+            Node::Text(text) => push_text(output, text),
+            Node::Element(element) => {
+                let element_ref: ElementRef = match ElementRef::wrap(node) {
+                    Some(element_ref) => element_ref,
+                    None => return
+                };
+                let name: &str = element.name();
+
+                match name {
+                    _ if SKIPPED_ELEMENTS.contains(&name) => {},
+                    "br" => push_line_break(output),
+                    "hr" => push_paragraph_break(output),
+                    "b" | "strong" => {
+                        let inner: String = self.render_inner(node);
+                        push_wrapped(output, &inner, "**");
+                    },
+                    "i" | "em" => {
+                        let inner: String = self.render_inner(node);
+                        push_wrapped(output, &inner, "*");
+                    },
+                    "u" | "ins" => {
+                        let inner: String = self.render_inner(node);
+                        push_wrapped(output, &inner, "__");
+                    },
+                    "s" | "strike" | "del" => {
+                        let inner: String = self.render_inner(node);
+                        push_wrapped(output, &inner, "~~");
+                    },
+                    "code" => {
+                        let code: String = element_ref.text().collect::<String>().replace('`', "'");
+                        push_wrapped(output, &code, "`");
+                    },
+                    "a" => self.render_link(element_ref, output),
+                    "ul" => self.render_list(element_ref, false, output),
+                    "ol" => self.render_list(element_ref, true, output),
+                    "li" => {
+                        // stavka bez liste oko sebe
+                        push_line_break_if_needed(output);
+                        output.push_str("- ");
+                        self.render_children(node, output);
+                        push_line_break(output);
+                    },
+                    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                        push_paragraph_break(output);
+                        let inner: String = self.render_inner(node);
+                        push_wrapped(output, &inner, "**");
+                        push_paragraph_break(output);
+                    },
+                    "td" | "th" => {
+                        self.render_children(node, output);
+                        output.push(' ');
+                    },
+                    _ if BLOCK_ELEMENTS.contains(&name) => {
+                        push_paragraph_break(output);
+                        self.render_children(node, output);
+                        push_paragraph_break(output);
+                    },
+                    _ => self.render_children(node, output)
+                };
+            },
+            _ => {}
+        };
+    }
+}
+
+fn push_line_break_if_needed(output: &mut String) {
+    if !output.is_empty() && !output.ends_with('\n') {
+        push_line_break(output);
+    }
+}
+
+// html podstablo elementa kao discord markdown
+// relativni linkovi se razresavaju u odnosu na `base_url`
+pub fn html_to_markdown(element: ElementRef, base_url: Option<&Url>) -> String {
+    let mut converter: Converter = Converter { base_url, list_depth: 0 };
+    let mut output: String = String::new();
+    converter.render_children(*element, &mut output);
+
+    // najvise jedan prazan red zaredom, bez razmaka na krajevima redova
+    let mut markdown: String = String::new();
+    let mut empty_lines: usize = 0;
+    for line in output.lines() {
+        let line: &str = line.trim_end();
+        if line.trim().is_empty() {
+            empty_lines += 1;
+            if empty_lines > 1 {
+                continue;
+            }
+        } else {
+            empty_lines = 0;
+        }
+        markdown.push_str(line);
+        markdown.push('\n');
+    }
+
+    return markdown.trim().to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use scraper::Html;
+
+    use super::*;
+
+    fn convert(html: &str) -> String {
+        let base_url: Url = Url::parse("https://sip.elfak.ni.ac.rs/").unwrap();
+        let fragment: Html = Html::parse_fragment(html);
+        return html_to_markdown(fragment.root_element(), Some(&base_url));
+    }
+
+    #[test]
+    fn nested_lists_are_indented() {
+        let html: &str = "<ul><li>Prva<ul><li>Pod 1</li><li>Pod 2</li></ul></li><li>Druga</li></ul><p>Posle</p>";
+        assert_eq!(convert(html), "- Prva\n  - Pod 1\n  - Pod 2\n- Druga\n\nPosle");
+    }
+
+    #[test]
+    fn ordered_list_inside_unordered() {
+        let html: &str = "<p>Rokovi:</p><ul><li>Jun<ol start=\"3\"><li>prvi</li><li>drugi</li></ol></li></ul>";
+        assert_eq!(convert(html), "Rokovi:\n\n- Jun\n  3. prvi\n  4. drugi");
+    }
+
+    #[test]
+    fn link_with_parentheses() {
+        assert_eq!(
+            convert("<p>Vidi <a href=\"/files/Raspored_(jun).pdf\">raspored (jun)</a>.</p>"),
+            "Vidi [raspored (jun)](https://sip.elfak.ni.ac.rs/files/Raspored_%28jun%29.pdf)."
+        );
+    }
+
+    #[test]
+    fn link_label_without_brackets() {
+        assert_eq!(convert("<a href=\"https://elfak.ni.ac.rs/a\">[PDF] raspored</a>"), "[PDF raspored](https://elfak.ni.ac.rs/a)");
+    }
+
+    #[test]
+    fn link_without_label_or_target() {
+        assert_eq!(convert("<a href=\"https://elfak.ni.ac.rs/\">https://elfak.ni.ac.rs/</a>"), "https://elfak.ni.ac.rs/");
+        assert_eq!(convert("<a href=\"#vrh\">na vrh</a>"), "na vrh");
+        assert_eq!(convert("<a href=\"javascript:void(0)\">klik</a>"), "klik");
+    }
+
+    #[test]
+    fn markers_hug_the_text() {
+        assert_eq!(convert("<p>Rok je <b> sutra </b>u 10h</p>"), "Rok je **sutra** u 10h");
+        assert_eq!(convert("<p><i>iskoseno</i> i <s>precrtano</s></p>"), "*iskoseno* i ~~precrtano~~");
+    }
+
+    #[test]
+    fn markdown_characters_are_escaped() {
+        assert_eq!(convert("<p>ispit_2023 *vazno*</p>"), "ispit\\_2023 \\*vazno\\*");
+    }
+
+    #[test]
+    fn paragraphs_and_line_breaks() {
+        assert_eq!(convert("<p>Prvi<br>red</p><p></p><p></p><div>Drugi</div>"), "Prvi\nred\n\nDrugi");
+    }
+}
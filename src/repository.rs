@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serenity::client::Context;
+use serenity::model::webhook::Webhook;
+use serenity::prelude::TypeMapKey;
+use tokio::sync::Mutex;
+
+use crate::archive::ArchivedPost;
+use crate::delivery::{DeliveredMessage, Delivery, OutboxEntry};
+use crate::errors::SipError;
+use crate::fetcher::SipPost;
+use crate::flood::HeldBatch;
+use crate::sources::{PostSource, Validators};
+use crate::webhooks::PrunedWebhook;
+
+// stanje bota: pretplate, stanje kolona, poruke i arhiva
+// SQLite implementacija je u database.rs, MemoryRepository je samo za testove
+pub trait Repository: Send {
+    // pretplate, po redosledu dodavanja
+    fn webhooks(&self) -> Result<Vec<Webhook>, SipError>;
+    // dodaje novu pretplatu ili osvezava postojecu na istom mestu
+    fn save_webhook(&mut self, webhook: &Webhook) -> Result<(), SipError>;
+    fn delete_webhook(&mut self, webhook_id: u64) -> Result<(), SipError>;
+
+    // uloge koje se pominju uz vazne poruke, po webhook-u
+    fn ping_roles(&self) -> Result<HashMap<u64, u64>, SipError>;
+    // None uklanja ulogu
+    fn set_ping_role(&mut self, webhook_id: u64, role_id: Option<u64>) -> Result<(), SipError>;
+
+    // webhook-ovi uklonjeni jer vise ne postoje na discord-u
+    fn add_pruned_webhook(&mut self, pruned: &PrunedWebhook) -> Result<(), SipError>;
+
+    // poslednje stanje kolone, prazno ako kolona jos nije procitana
+    fn snapshot(&self, snapshot_key: &str) -> Result<Vec<SipPost>, SipError>;
+    fn set_snapshot(&mut self, snapshot_key: &str, posts: &[SipPost]) -> Result<(), SipError>;
+
+    // hesevi sadrzaja procitanih postova izvora, po kljucu posta
+    fn post_hashes(&self, source: &dyn PostSource) -> Result<HashMap<String, String>, SipError>;
+    // upisuje navedene heseve, ostali ostaju
+    fn save_post_hashes(&mut self, source: &dyn PostSource, hashes: &HashMap<String, String>) -> Result<(), SipError>;
+    fn delete_post_hashes(&mut self, source: &dyn PostSource, post_keys: &[String]) -> Result<(), SipError>;
+
+    // podaci za uslovni zahtev izvora, podrazumevani ako izvor jos nije procitan
+    fn validators(&self, source: &dyn PostSource) -> Result<Validators, SipError>;
+    fn set_validators(&mut self, source: &dyn PostSource, validators: &Validators) -> Result<(), SipError>;
+
+    // poruke koje cekaju slanje, po redosledu upisa
+    fn outbox(&self) -> Result<Vec<OutboxEntry>, SipError>;
+    // nova poruka dobija id veci od svih prethodnih
+    fn insert_delivery(&mut self, webhook_id: u64, delivery: &Delivery, next_attempt_at: i64) -> Result<u64, SipError>;
+    fn update_delivery(&mut self, id: u64, delivery: &Delivery) -> Result<(), SipError>;
+    fn reschedule_delivery(&mut self, id: u64, attempts: u32, next_attempt_at: i64) -> Result<(), SipError>;
+    fn delete_delivery(&mut self, id: u64) -> Result<(), SipError>;
+    fn delete_webhook_deliveries(&mut self, webhook_id: u64) -> Result<(), SipError>;
+
+    // poslate poruke sa embedima postova, za kasnije izmene
+    fn delivered_messages(&self, webhook_id: u64, post_key: &str) -> Result<Vec<DeliveredMessage>, SipError>;
+    fn add_delivered_messages(&mut self, messages: &[DeliveredMessage]) -> Result<(), SipError>;
+    fn delete_delivered_messages(&mut self, webhook_id: u64, post_key: &str) -> Result<(), SipError>;
+    // poruke postova koji se vise ne menjaju, za sve webhook-ove
+    fn delete_post_messages(&mut self, post_keys: &[String]) -> Result<(), SipError>;
+    fn delete_webhook_messages(&mut self, webhook_id: u64) -> Result<(), SipError>;
+    // za promenu oblika kljuceva postova
+    fn delivered_post_keys(&self) -> Result<Vec<String>, SipError>;
+    fn rename_delivered_post(&mut self, old_key: &str, new_key: &str) -> Result<(), SipError>;
+
+    // poruke zadrzane zbog prevelikog broja novih postova
+    fn held_batches(&self) -> Result<Vec<HeldBatch>, SipError>;
+    fn next_held_batch_id(&mut self) -> Result<u64, SipError>;
+    fn save_held_batch(&mut self, batch: &HeldBatch) -> Result<(), SipError>;
+    // uklanja i vraca zadrzane poruke
+    fn take_held_batch(&mut self, id: u64) -> Result<Option<HeldBatch>, SipError>;
+
+    // arhiva postova, cuvanje ne brise postove koji nisu navedeni
+    fn archived_posts(&self) -> Result<HashMap<String, ArchivedPost>, SipError>;
+    // postovi objavljeni u periodu [from, to), unix vremena
+    fn archived_posts_published(&self, from: i64, to: i64) -> Result<Vec<ArchivedPost>, SipError>;
+    fn archived_post(&self, key: &str) -> Result<Option<ArchivedPost>, SipError>;
+    fn save_archived_posts(&mut self, posts: &HashMap<String, ArchivedPost>) -> Result<(), SipError>;
+    fn delete_archived_post(&mut self, key: &str) -> Result<(), SipError>;
+
+    // rezervna kopija pre novih upisa, ako je prosla dovoljno stara
+    fn backup(&mut self) -> Result<(), SipError>;
+
+    // izmene izmedju begin i commit se upisuju zajedno, rollback ih ponistava
+    // moze da se ugnjezdi, vidi `atomically`
+    fn begin(&mut self) -> Result<(), SipError>;
+    fn commit(&mut self) -> Result<(), SipError>;
+    fn rollback(&mut self) -> Result<(), SipError>;
+}
+
+impl dyn Repository + '_ {
+    // upisuje sve izmene iz `work` ili nijednu, npr. jedno citanje izvora
+    pub fn atomically<T>(&mut self, work: impl FnOnce(&mut dyn Repository) -> Result<T, SipError>) -> Result<T, SipError> {
+        self.begin()?;
+        let result: Result<T, SipError> = work(self).and_then(|value| self.commit().map(|_| value));
+        if result.is_err() {
+            let _ = self.rollback();
+        }
+        return result;
+    }
+}
+
+// zajednicko stanje za bota, citac i slanje poruka
+pub type SharedRepository = Arc<Mutex<Box<dyn Repository>>>;
+
+pub fn shared(repository: Box<dyn Repository>) -> SharedRepository {
+    return Arc::new(Mutex::new(repository));
+}
+
+// stanje dostupno komandama kroz `Context::data`
+pub struct RepositoryKey;
+
+impl TypeMapKey for RepositoryKey {
+    type Value = SharedRepository;
+}
+
+pub async fn from_context(context: &Context) -> Result<SharedRepository, SipError> {
+    return context.data.read().await
+        .get::<RepositoryKey>()
+        .cloned()
+        .ok_or_else(|| SipError::StorageError("Repository is not registered".to_string()));
+}
+
+// stanje samo u memoriji, za testove bez baze
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub struct MemoryRepository {
+    webhooks: Vec<Webhook>,
+    ping_roles: HashMap<u64, u64>,
+    pruned_webhooks: Vec<PrunedWebhook>,
+    snapshots: HashMap<String, Vec<SipPost>>,
+    // po id-u izvora
+    hashes: HashMap<String, HashMap<String, String>>,
+    validators: HashMap<String, Validators>,
+    outbox: Vec<OutboxEntry>,
+    next_delivery_id: u64,
+    delivered_messages: Vec<DeliveredMessage>,
+    held_batches: Vec<HeldBatch>,
+    next_held_batch_id: u64,
+    archive: HashMap<String, ArchivedPost>,
+    // stanje pre svakog otvorenog begin
+    saved: Vec<MemoryRepository>
+}
+
+#[cfg(test)]
+impl Repository for MemoryRepository {
+    fn webhooks(&self) -> Result<Vec<Webhook>, SipError> {
+        return Ok(self.webhooks.clone());
+    }
+
+    fn save_webhook(&mut self, webhook: &Webhook) -> Result<(), SipError> {
+        match self.webhooks.iter_mut().find(|stored| stored.id == webhook.id) {
+            Some(stored) => *stored = webhook.clone(),
+            None => self.webhooks.push(webhook.clone())
+        };
+        return Ok(());
+    }
+
+    fn delete_webhook(&mut self, webhook_id: u64) -> Result<(), SipError> {
+        self.webhooks.retain(|webhook| webhook.id.0 != webhook_id);
+        return Ok(());
+    }
+
+    fn ping_roles(&self) -> Result<HashMap<u64, u64>, SipError> {
+        return Ok(self.ping_roles.clone());
+    }
+
+    fn set_ping_role(&mut self, webhook_id: u64, role_id: Option<u64>) -> Result<(), SipError> {
+        match role_id {
+            Some(role_id) => self.ping_roles.insert(webhook_id, role_id),
+            None => self.ping_roles.remove(&webhook_id)
+        };
+        return Ok(());
+    }
+
+    fn add_pruned_webhook(&mut self, pruned: &PrunedWebhook) -> Result<(), SipError> {
+        self.pruned_webhooks.push(pruned.clone());
+        return Ok(());
+    }
+
+    fn snapshot(&self, snapshot_key: &str) -> Result<Vec<SipPost>, SipError> {
+        return Ok(self.snapshots.get(snapshot_key).cloned().unwrap_or_default());
+    }
+
+    fn set_snapshot(&mut self, snapshot_key: &str, posts: &[SipPost]) -> Result<(), SipError> {
+        self.snapshots.insert(snapshot_key.to_string(), posts.to_vec());
+        return Ok(());
+    }
+
+    fn post_hashes(&self, source: &dyn PostSource) -> Result<HashMap<String, String>, SipError> {
+        return Ok(self.hashes.get(source.id()).cloned().unwrap_or_default());
+    }
+
+    fn save_post_hashes(&mut self, source: &dyn PostSource, hashes: &HashMap<String, String>) -> Result<(), SipError> {
+        self.hashes.entry(source.id().to_string()).or_default().extend(hashes.iter().map(|(key, hash)| (key.clone(), hash.clone())));
+        return Ok(());
+    }
+
+    fn delete_post_hashes(&mut self, source: &dyn PostSource, post_keys: &[String]) -> Result<(), SipError> {
+        if let Some(hashes) = self.hashes.get_mut(source.id()) {
+            hashes.retain(|key, _| !post_keys.contains(key));
+        }
+        return Ok(());
+    }
+
+    fn validators(&self, source: &dyn PostSource) -> Result<Validators, SipError> {
+        return Ok(self.validators.get(source.id()).cloned().unwrap_or_default());
+    }
+
+    fn set_validators(&mut self, source: &dyn PostSource, validators: &Validators) -> Result<(), SipError> {
+        self.validators.insert(source.id().to_string(), validators.clone());
+        return Ok(());
+    }
+
+    fn outbox(&self) -> Result<Vec<OutboxEntry>, SipError> {
+        return Ok(self.outbox.clone());
+    }
+
+    fn insert_delivery(&mut self, webhook_id: u64, delivery: &Delivery, next_attempt_at: i64) -> Result<u64, SipError> {
+        self.next_delivery_id += 1;
+        self.outbox.push(OutboxEntry { id: self.next_delivery_id, webhook_id, delivery: delivery.clone(), attempts: 0, next_attempt_at });
+        return Ok(self.next_delivery_id);
+    }
+
+    fn update_delivery(&mut self, id: u64, delivery: &Delivery) -> Result<(), SipError> {
+        if let Some(entry) = self.outbox.iter_mut().find(|entry| entry.id == id) {
+            entry.delivery = delivery.clone();
+        }
+        return Ok(());
+    }
+
+    fn reschedule_delivery(&mut self, id: u64, attempts: u32, next_attempt_at: i64) -> Result<(), SipError> {
+        if let Some(entry) = self.outbox.iter_mut().find(|entry| entry.id == id) {
+            entry.attempts = attempts;
+            entry.next_attempt_at = next_attempt_at;
+        }
+        return Ok(());
+    }
+
+    fn delete_delivery(&mut self, id: u64) -> Result<(), SipError> {
+        self.outbox.retain(|entry| entry.id != id);
+        return Ok(());
+    }
+
+    fn delete_webhook_deliveries(&mut self, webhook_id: u64) -> Result<(), SipError> {
+        self.outbox.retain(|entry| entry.webhook_id != webhook_id);
+        return Ok(());
+    }
+
+    fn delivered_messages(&self, webhook_id: u64, post_key: &str) -> Result<Vec<DeliveredMessage>, SipError> {
+        return Ok(self.delivered_messages
+            .iter()
+            .filter(|delivered| delivered.webhook_id == webhook_id && delivered.link == post_key)
+            .cloned()
+            .collect());
+    }
+
+    fn add_delivered_messages(&mut self, messages: &[DeliveredMessage]) -> Result<(), SipError> {
+        self.delivered_messages.extend(messages.iter().cloned());
+        return Ok(());
+    }
+
+    fn delete_delivered_messages(&mut self, webhook_id: u64, post_key: &str) -> Result<(), SipError> {
+        self.delivered_messages.retain(|delivered| !(delivered.webhook_id == webhook_id && delivered.link == post_key));
+        return Ok(());
+    }
+
+    fn delete_post_messages(&mut self, post_keys: &[String]) -> Result<(), SipError> {
+        self.delivered_messages.retain(|delivered| !post_keys.contains(&delivered.link));
+        return Ok(());
+    }
+
+    fn delete_webhook_messages(&mut self, webhook_id: u64) -> Result<(), SipError> {
+        self.delivered_messages.retain(|delivered| delivered.webhook_id != webhook_id);
+        return Ok(());
+    }
+
+    fn delivered_post_keys(&self) -> Result<Vec<String>, SipError> {
+        let mut keys: Vec<String> = self.delivered_messages.iter().map(|delivered| delivered.link.clone()).collect();
+        keys.sort();
+        keys.dedup();
+        return Ok(keys);
+    }
+
+    fn rename_delivered_post(&mut self, old_key: &str, new_key: &str) -> Result<(), SipError> {
+        for delivered in self.delivered_messages.iter_mut().filter(|delivered| delivered.link == old_key) {
+            delivered.link = new_key.to_string();
+        }
+        return Ok(());
+    }
+
+    fn held_batches(&self) -> Result<Vec<HeldBatch>, SipError> {
+        return Ok(self.held_batches.clone());
+    }
+
+    fn next_held_batch_id(&mut self) -> Result<u64, SipError> {
+        let id: u64 = self.next_held_batch_id;
+        self.next_held_batch_id += 1;
+        return Ok(id);
+    }
+
+    fn save_held_batch(&mut self, batch: &HeldBatch) -> Result<(), SipError> {
+        match self.held_batches.iter_mut().find(|held| held.id == batch.id) {
+            Some(held) => *held = batch.clone(),
+            None => self.held_batches.push(batch.clone())
+        };
+        return Ok(());
+    }
+
+    fn take_held_batch(&mut self, id: u64) -> Result<Option<HeldBatch>, SipError> {
+        return Ok(self.held_batches.iter().position(|batch| batch.id == id).map(|position| self.held_batches.remove(position)));
+    }
+
+    fn archived_posts(&self) -> Result<HashMap<String, ArchivedPost>, SipError> {
+        return Ok(self.archive.clone());
+    }
+
+    fn archived_posts_published(&self, from: i64, to: i64) -> Result<Vec<ArchivedPost>, SipError> {
+        let mut posts: Vec<ArchivedPost> = self.archive
+            .values()
+            .filter(|archived| archived.post.published_at.is_some_and(|published_at| (from..to).contains(&published_at.timestamp())))
+            .cloned()
+            .collect();
+        posts.sort_by_key(|archived| archived.post.published_at);
+        return Ok(posts);
+    }
+
+    fn archived_post(&self, key: &str) -> Result<Option<ArchivedPost>, SipError> {
+        return Ok(self.archive.get(key).cloned());
+    }
+
+    fn save_archived_posts(&mut self, posts: &HashMap<String, ArchivedPost>) -> Result<(), SipError> {
+        self.archive.extend(posts.iter().map(|(key, archived)| (key.clone(), archived.clone())));
+        return Ok(());
+    }
+
+    fn delete_archived_post(&mut self, key: &str) -> Result<(), SipError> {
+        self.archive.remove(key);
+        return Ok(());
+    }
+
+    fn backup(&mut self) -> Result<(), SipError> {
+        return Ok(());
+    }
+
+    fn begin(&mut self) -> Result<(), SipError> {
+        let mut saved: MemoryRepository = self.clone();
+        saved.saved.clear();
+        self.saved.push(saved);
+        return Ok(());
+    }
+
+    fn commit(&mut self) -> Result<(), SipError> {
+        self.saved.pop();
+        return Ok(());
+    }
+
+    fn rollback(&mut self) -> Result<(), SipError> {
+        if let Some(saved) = self.saved.pop() {
+            let outer: Vec<MemoryRepository> = std::mem::take(&mut self.saved);
+            *self = saved;
+            self.saved = outer;
+        }
+        return Ok(());
+    }
+}
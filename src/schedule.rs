@@ -0,0 +1,227 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use rand::Rng;
+
+use crate::config::CONFIG;
+use crate::dates::parse_sip_date;
+use crate::errors::SipError;
+use crate::logger::TIMEZONE;
+
+// raspored citanja izvora
+// SIP_POLL_SCHEDULE: "15m" (fiksni interval), "cron:0 */10 * * * *" ili "adaptive"
+pub enum PollSchedule {
+    Fixed(Duration),
+    Cron(Box<cron::Schedule>),
+    Adaptive(AdaptiveSchedule)
+}
+
+// brze citanje radnim danima u radno vreme i oko ispitnih rokova
+// sporije nocu i vikendom
+pub struct AdaptiveSchedule {
+    pub fast: Duration,
+    pub normal: Duration,
+    pub slow: Duration,
+    // radno vreme [pocetak, kraj) u satima
+    pub work_hours: (u32, u32),
+    // ispitni rokovi, prosireni za SIP_EXAM_MARGIN_DAYS dana sa obe strane
+    pub exam_periods: Vec<(NaiveDate, NaiveDate)>
+}
+
+// noc: od 22 do 6 casova
+const NIGHT_START: u32 = 22;
+const NIGHT_END: u32 = 6;
+
+// "30s", "15m", "1h" ili broj sekundi
+pub fn parse_duration(raw: &str) -> Option<Duration> {
+    let raw: &str = raw.trim();
+    let (number, multiplier): (&str, u64) = match raw.chars().last()? {
+        's' => (&raw[..raw.len() - 1], 1),
+        'm' => (&raw[..raw.len() - 1], 60),
+        'h' => (&raw[..raw.len() - 1], 60 * 60),
+        _ => (raw, 1)
+    };
+    let seconds: u64 = number.trim().parse::<u64>().ok()?.checked_mul(multiplier)?;
+    if seconds == 0 {
+        return None;
+    }
+    return Some(Duration::from_secs(seconds));
+}
+
+fn parse_duration_setting(name: &str, raw: &str) -> Result<Duration, SipError> {
+    return parse_duration(raw).ok_or_else(|| SipError::ConfigError(format!("Invalid {}: {}", name, raw)));
+}
+
+// "7-20"
+fn parse_work_hours(raw: &str) -> Result<(u32, u32), SipError> {
+    let error = || SipError::ConfigError(format!("Invalid SIP_WORK_HOURS: {}", raw));
+    let (start, end) = raw.split_once('-').ok_or_else(error)?;
+    let start: u32 = start.trim().parse::<u32>().map_err(|_| error())?;
+    let end: u32 = end.trim().parse::<u32>().map_err(|_| error())?;
+    if start >= end || end > 24 {
+        return Err(error());
+    }
+    return Ok((start, end));
+}
+
+// "15.01.2024..10.02.2024,10.06.2024..05.07.2024"
+fn parse_exam_periods(raw: &str, margin_days: i64) -> Result<Vec<(NaiveDate, NaiveDate)>, SipError> {
+    let mut periods: Vec<(NaiveDate, NaiveDate)> = Vec::<(NaiveDate, NaiveDate)>::new();
+
+    for period in raw.split(',').map(|period| period.trim()).filter(|period| !period.is_empty()) {
+        let error = || SipError::ConfigError(format!("Invalid exam period in SIP_EXAM_PERIODS: {}", period));
+        let (start, end) = period.split_once("..").ok_or_else(error)?;
+        let start: NaiveDate = parse_sip_date(start).ok_or_else(error)?.date_naive();
+        let end: NaiveDate = parse_sip_date(end).ok_or_else(error)?.date_naive();
+        if start > end {
+            return Err(error());
+        }
+        periods.push((start - chrono::Duration::days(margin_days), end + chrono::Duration::days(margin_days)));
+    }
+
+    return Ok(periods);
+}
+
+impl PollSchedule {
+    pub fn from_config() -> Result<PollSchedule, SipError> {
+        let raw: &str = CONFIG.poll_schedule.trim();
+
+        if let Some(expression) = raw.strip_prefix("cron:") {
+            let schedule: cron::Schedule = cron::Schedule::from_str(expression.trim()).map_err(|err| SipError::ConfigError(format!("Invalid cron expression in SIP_POLL_SCHEDULE: {}", err)))?;
+            return Ok(PollSchedule::Cron(Box::new(schedule)));
+        }
+
+        if raw == "adaptive" {
+            return Ok(PollSchedule::Adaptive(AdaptiveSchedule {
+                fast: parse_duration_setting("SIP_POLL_FAST", &CONFIG.poll_fast)?,
+                normal: parse_duration_setting("SIP_POLL_NORMAL", &CONFIG.poll_normal)?,
+                slow: parse_duration_setting("SIP_POLL_SLOW", &CONFIG.poll_slow)?,
+                work_hours: parse_work_hours(&CONFIG.work_hours)?,
+                exam_periods: parse_exam_periods(&CONFIG.exam_periods, CONFIG.exam_margin_days)?
+            }));
+        }
+
+        return Ok(PollSchedule::Fixed(parse_duration_setting("SIP_POLL_SCHEDULE", raw)?));
+    }
+
+    // vreme do sledeceg citanja, bez slucajnog odstupanja
+    pub fn next_delay(&self, now: DateTime<Tz>) -> Duration {
+        return match self {
+            PollSchedule::Fixed(interval) => *interval,
+            PollSchedule::Cron(schedule) => schedule
+                .after(&now)
+                .next()
+                .and_then(|next| (next - now).to_std().ok())
+                .unwrap_or(Duration::from_secs(15 * 60)),
+            PollSchedule::Adaptive(adaptive) => adaptive.interval_at(now)
+        };
+    }
+}
+
+impl AdaptiveSchedule {
+    pub fn interval_at(&self, now: DateTime<Tz>) -> Duration {
+        let hour: u32 = now.hour();
+        let date: NaiveDate = now.date_naive();
+
+        if !(NIGHT_END..NIGHT_START).contains(&hour) {
+            return self.slow;
+        }
+        if self.exam_periods.iter().any(|(start, end)| *start <= date && date <= *end) {
+            return self.fast;
+        }
+        if matches!(now.weekday(), Weekday::Sat | Weekday::Sun) {
+            return self.slow;
+        }
+        if (self.work_hours.0..self.work_hours.1).contains(&hour) {
+            return self.fast;
+        }
+        return self.normal;
+    }
+}
+
+// sledece cekanje sa slucajnim odstupanjem od 0 do SIP_POLL_JITTER sekundi
+pub fn next_poll_delay(schedule: &PollSchedule) -> Duration {
+    let now: DateTime<Tz> = Utc::now().with_timezone(&TIMEZONE);
+    let jitter: u64 = if CONFIG.poll_jitter > 0 { rand::thread_rng().gen_range(0..=CONFIG.poll_jitter) } else { 0 };
+    return schedule.next_delay(now) + Duration::from_secs(jitter);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule() -> AdaptiveSchedule {
+        return AdaptiveSchedule {
+            fast: Duration::from_secs(5 * 60),
+            normal: Duration::from_secs(15 * 60),
+            slow: Duration::from_secs(60 * 60),
+            work_hours: (7, 20),
+            exam_periods: parse_exam_periods("15.01.2024..10.02.2024", 7).unwrap()
+        };
+    }
+
+    fn at(day: u32, month: u32, hour: u32) -> DateTime<Tz> {
+        return TIMEZONE.with_ymd_and_hms(2024, month, day, hour, 0, 0).unwrap();
+    }
+
+    #[test]
+    fn weekdays_outside_exam_periods() {
+        let schedule: AdaptiveSchedule = schedule();
+        // ponedeljak, 04.03.2024.
+        assert_eq!(schedule.interval_at(at(4, 3, 7)), schedule.fast);
+        assert_eq!(schedule.interval_at(at(4, 3, 19)), schedule.fast);
+        assert_eq!(schedule.interval_at(at(4, 3, 20)), schedule.normal);
+        assert_eq!(schedule.interval_at(at(4, 3, 6)), schedule.normal);
+        assert_eq!(schedule.interval_at(at(4, 3, 22)), schedule.slow);
+        assert_eq!(schedule.interval_at(at(4, 3, 3)), schedule.slow);
+    }
+
+    #[test]
+    fn weekend_during_exam_period() {
+        let schedule: AdaptiveSchedule = schedule();
+        // subota, 20.01.2024., tokom roka
+        assert_eq!(schedule.interval_at(at(20, 1, 12)), schedule.fast);
+        assert_eq!(schedule.interval_at(at(20, 1, 21)), schedule.fast);
+        // noc ostaje spora i tokom roka
+        assert_eq!(schedule.interval_at(at(20, 1, 23)), schedule.slow);
+        assert_eq!(schedule.interval_at(at(21, 1, 5)), schedule.slow);
+        // subota, 24.02.2024., posle roka
+        assert_eq!(schedule.interval_at(at(24, 2, 12)), schedule.slow);
+    }
+
+    #[test]
+    fn exam_margin_is_inclusive() {
+        let schedule: AdaptiveSchedule = schedule();
+        // rok sa 7 dana sa obe strane: 08.01.2024. - 17.02.2024.
+        assert_eq!(schedule.exam_periods, vec![(NaiveDate::from_ymd_opt(2024, 1, 8).unwrap(), NaiveDate::from_ymd_opt(2024, 2, 17).unwrap())]);
+        // nedelja pre roka i subota na kraju roka
+        assert_eq!(schedule.interval_at(at(7, 1, 12)), schedule.slow);
+        assert_eq!(schedule.interval_at(at(17, 2, 12)), schedule.fast);
+        assert_eq!(schedule.interval_at(at(18, 2, 12)), schedule.slow);
+        // radnim danom van radnog vremena rok ubrzava citanje
+        assert_eq!(schedule.interval_at(at(24, 1, 21)), schedule.fast);
+    }
+
+    #[test]
+    fn invalid_exam_periods() {
+        assert!(parse_exam_periods("", 7).unwrap().is_empty());
+        assert!(parse_exam_periods("10.02.2024..15.01.2024", 7).is_err());
+        assert!(parse_exam_periods("15.01.2024", 7).is_err());
+        assert!(parse_exam_periods("15.01.2024..juce", 7).is_err());
+    }
+
+    #[test]
+    fn durations_and_work_hours() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration(" 15m "), Some(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(60 * 60)));
+        assert_eq!(parse_duration("300"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_work_hours("7-20").ok(), Some((7, 20)));
+        assert!(parse_work_hours("20-7").is_err());
+        assert!(parse_work_hours("7-25").is_err());
+    }
+}
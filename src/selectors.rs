@@ -0,0 +1,255 @@
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use scraper::Selector;
+use serde::{Serialize, Deserialize};
+
+use crate::config::CONFIG;
+use crate::errors::SipError;
+use crate::fetcher::{hash_bytes, SipPostType};
+use crate::logger;
+
+// css selektori za sip stranicu, ucitavaju se iz SIP_SELECTORS_FILE (json)
+// ako fajl ne postoji koriste se podrazumevani selektori
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SelectorConfig {
+    // kolone sa postovima
+    pub columns: String,
+    // tip postova u svakoj koloni, redom
+    pub column_types: Vec<SipPostType>,
+    // jedan post unutar kolone
+    pub posts: String,
+    // delovi posta i redni broj elementa koji se uzima
+    pub date: String,
+    pub date_nth: usize,
+    pub title: String,
+    pub title_nth: usize,
+    pub content: String,
+    pub content_nth: usize,
+    pub link: String,
+    pub link_nth: usize,
+    // stranica posta
+    pub article: String,
+    pub file_link: String
+}
+
+impl Default for SelectorConfig {
+    fn default() -> SelectorConfig {
+        return SelectorConfig {
+            columns: "div.news-box".to_string(),
+            column_types: vec![SipPostType::New, SipPostType::Important],
+            posts: "ul li".to_string(),
+            date: "p".to_string(),
+            date_nth: 0,
+            title: "h4".to_string(),
+            title_nth: 0,
+            content: "p".to_string(),
+            content_nth: 1,
+            link: "a".to_string(),
+            link_nth: 0,
+            article: "article, div.news-box, div.content, main".to_string(),
+            file_link: "a[href]".to_string()
+        };
+    }
+}
+
+pub struct PostSelectors {
+    // izvorni tekst selektora, za poruke o greskama
+    pub raw: SelectorConfig,
+    // hes podesavanja, menja se sa svakom izmenom selektora
+    pub hash: String,
+    pub columns: Selector,
+    pub column_types: Vec<SipPostType>,
+    pub posts: Selector,
+    pub date: Selector,
+    pub date_nth: usize,
+    pub title: Selector,
+    pub title_nth: usize,
+    pub content: Selector,
+    pub content_nth: usize,
+    pub link: Selector,
+    pub link_nth: usize,
+    pub article: Selector,
+    pub file_link: Selector
+}
+
+fn compile(field: &str, raw: &str) -> Result<Selector, SipError> {
+    return Selector::parse(raw).map_err(|err| SipError::SelectorError(format!("Invalid selector `{}` = \"{}\": {}", field, raw, err)));
+}
+
+impl PostSelectors {
+    pub fn from_config(config: &SelectorConfig) -> Result<PostSelectors, SipError> {
+        if config.column_types.is_empty() {
+            return Err(SipError::SelectorError("`column_types` must contain at least one post type".to_string()));
+        }
+        for (index, post_type) in config.column_types.iter().enumerate() {
+            if config.column_types[..index].contains(post_type) {
+                return Err(SipError::SelectorError(format!("`column_types` contains {:?} more than once", post_type)));
+            }
+        }
+
+        let raw_json: String = serde_json::to_string(config).map_err(|err| SipError::SelectorError(err.to_string()))?;
+        return Ok(PostSelectors {
+            raw: config.clone(),
+            hash: hash_bytes(raw_json.bytes()),
+            columns: compile("columns", &config.columns)?,
+            column_types: config.column_types.clone(),
+            posts: compile("posts", &config.posts)?,
+            date: compile("date", &config.date)?,
+            date_nth: config.date_nth,
+            title: compile("title", &config.title)?,
+            title_nth: config.title_nth,
+            content: compile("content", &config.content)?,
+            content_nth: config.content_nth,
+            link: compile("link", &config.link)?,
+            link_nth: config.link_nth,
+            article: compile("article", &config.article)?,
+            file_link: compile("file_link", &config.file_link)?
+        });
+    }
+}
+
+struct LoadedSelectors {
+    selectors: Arc<PostSelectors>,
+    // vreme izmene ucitanog fajla, None za podrazumevane selektore
+    modified: Option<SystemTime>
+}
+
+lazy_static::lazy_static! {
+    static ref SELECTORS: RwLock<LoadedSelectors> = RwLock::new(LoadedSelectors {
+        selectors: Arc::new(PostSelectors::from_config(&SelectorConfig::default()).unwrap()),
+        modified: None
+    });
+}
+
+fn file_modified(path: &str) -> Option<SystemTime> {
+    return std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+}
+
+fn read_selectors_file(path: &str) -> Result<PostSelectors, SipError> {
+    let raw: String = std::fs::read_to_string(path).map_err(|err| SipError::FileSystemError(format!("{}: {}", path, err)))?;
+    let config: SelectorConfig = serde_json::from_str::<SelectorConfig>(&raw).map_err(|err| SipError::SelectorError(format!("{}: {}", path, err)))?;
+    return PostSelectors::from_config(&config).map_err(|err| SipError::SelectorError(format!("{}: {}", path, err)));
+}
+
+// trenutni selektori
+pub fn current() -> Arc<PostSelectors> {
+    return SELECTORS.read().unwrap().selectors.clone();
+}
+
+// ucitava selektore pri pokretanju, neispravan fajl zaustavlja bota
+pub fn setup_selectors() -> Result<(), SipError> {
+    let modified: Option<SystemTime> = file_modified(&CONFIG.selectors_file);
+    if modified.is_none() {
+        logger::log_sync("SLCTR", format!("{} not found, using default selectors", CONFIG.selectors_file));
+        return Ok(());
+    }
+
+    let selectors: PostSelectors = read_selectors_file(&CONFIG.selectors_file)?;
+    *SELECTORS.write().unwrap() = LoadedSelectors { selectors: Arc::new(selectors), modified };
+    logger::log_sync("SLCTR", format!("Selectors loaded from {}", CONFIG.selectors_file));
+
+    return Ok(());
+}
+
+// ponovo ucitava fajl ako je izmenjen, None ako nije
+// neispravan fajl vraca gresku, a zadrzavaju se prethodni selektori
+fn reload(loaded: &RwLock<LoadedSelectors>, path: &str) -> Option<Result<(), SipError>> {
+    let modified: Option<SystemTime> = file_modified(path);
+    if modified.is_none() || modified == loaded.read().unwrap().modified {
+        return None;
+    }
+
+    return match read_selectors_file(path) {
+        Ok(selectors) => {
+            *loaded.write().unwrap() = LoadedSelectors { selectors: Arc::new(selectors), modified };
+            Some(Ok(()))
+        },
+        Err(why) => {
+            // pamti se vreme izmene da se ista greska ne prijavljuje pri svakom citanju
+            loaded.write().unwrap().modified = modified;
+            Some(Err(why))
+        }
+    };
+}
+
+pub async fn reload_if_changed() {
+    match reload(&SELECTORS, &CONFIG.selectors_file) {
+        Some(Ok(())) => logger::log("SLCTR", format!("Selectors reloaded from {}", CONFIG.selectors_file)).await,
+        Some(Err(why)) => logger::log("SLCTR", format!("Keeping previous selectors: {}", why)).await,
+        None => {}
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn default_selectors() -> RwLock<LoadedSelectors> {
+        return RwLock::new(LoadedSelectors {
+            selectors: Arc::new(PostSelectors::from_config(&SelectorConfig::default()).unwrap()),
+            modified: None
+        });
+    }
+
+    // upisuje fajl sa zadatim vremenom izmene, da izmena ne zavisi od preciznosti sata
+    fn write_file(path: &str, contents: &str, seconds: u64) {
+        fs::write(path, contents).unwrap();
+        fs::File::options().write(true).open(path).unwrap().set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)).unwrap();
+    }
+
+    #[test]
+    fn invalid_selector_is_rejected() {
+        let config: SelectorConfig = SelectorConfig { title: "h4[".to_string(), ..SelectorConfig::default() };
+
+        let error: String = PostSelectors::from_config(&config).err().unwrap().to_string();
+
+        assert!(error.contains("Invalid selector `title` = \"h4[\""), "{}", error);
+    }
+
+    #[test]
+    fn column_types_are_checked() {
+        let empty: SelectorConfig = SelectorConfig { column_types: Vec::<SipPostType>::new(), ..SelectorConfig::default() };
+        assert!(PostSelectors::from_config(&empty).err().unwrap().to_string().contains("at least one post type"));
+
+        let repeated: SelectorConfig = SelectorConfig { column_types: vec![SipPostType::New, SipPostType::New], ..SelectorConfig::default() };
+        assert!(PostSelectors::from_config(&repeated).err().unwrap().to_string().contains("more than once"));
+    }
+
+    #[test]
+    fn bad_reload_keeps_previous_selectors() {
+        let directory: TempDir = TempDir::new().unwrap();
+        let path: String = directory.path().join("selectors.json").display().to_string();
+        let loaded: RwLock<LoadedSelectors> = default_selectors();
+        let default_hash: String = loaded.read().unwrap().selectors.hash.clone();
+
+        // nema fajla, ostaju podrazumevani selektori
+        assert!(reload(&loaded, &path).is_none());
+
+        write_file(&path, r#"{"title": "h3"}"#, 1000);
+        assert!(reload(&loaded, &path).unwrap().is_ok());
+        let reloaded_hash: String = loaded.read().unwrap().selectors.hash.clone();
+        assert_ne!(reloaded_hash, default_hash);
+        assert_eq!(loaded.read().unwrap().selectors.raw.title, "h3");
+        // nepromenjen fajl se ne cita ponovo
+        assert!(reload(&loaded, &path).is_none());
+
+        write_file(&path, r#"{"title": "h3["}"#, 2000);
+        let error: String = reload(&loaded, &path).unwrap().err().unwrap().to_string();
+        assert!(error.contains("Invalid selector `title`"), "{}", error);
+        assert_eq!(loaded.read().unwrap().selectors.hash, reloaded_hash);
+        // ista greska se ne prijavljuje ponovo
+        assert!(reload(&loaded, &path).is_none());
+
+        write_file(&path, "{ nije json", 3000);
+        let error: String = reload(&loaded, &path).unwrap().err().unwrap().to_string();
+        assert!(error.contains(&path), "{}", error);
+        assert_eq!(loaded.read().unwrap().selectors.raw.title, "h3");
+    }
+}
@@ -0,0 +1,417 @@
+use std::sync::Arc;
+
+use reqwest::{Response, Url};
+use serde::{Serialize, Deserialize};
+use scraper::{Html, ElementRef};
+
+use crate::dates::parse_sip_date;
+use crate::errors::SipError;
+use crate::http_client;
+use crate::links::{post_key, resolve_link};
+use crate::markdown::html_to_markdown;
+use crate::selectors::{self, PostSelectors};
+use crate::fetcher::{SipAttachment, SipPost, SipPostType};
+
+// podaci sa stranice posta
+pub struct PostPage {
+    pub content: Option<String>,
+    pub attachments: Vec<SipAttachment>
+}
+
+// podaci za uslovne zahteve i hes poslednjeg procitanog sadrzaja
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub body_hash: Option<String>,
+    // hes selektora kojima je stranica procitana, vidi PostSource::parser_hash
+    #[serde(default)]
+    pub parser_hash: Option<String>
+}
+
+pub enum FetchOutcome {
+    // server je potvrdio da se stranica nije promenila
+    NotModified,
+    Modified {
+        body: String,
+        validators: Validators
+    }
+}
+
+// izvor postova (sip, stranice katedri, ...)
+// svaki izvor ima svoj identitet koji se koristi kao prostor imena u bazi
+#[serenity::async_trait]
+pub trait PostSource: Send + Sync {
+    fn id(&self) -> &str;
+
+    fn name(&self) -> &str;
+
+    fn url(&self) -> &str;
+
+    // kolone (tipovi postova) koje izvor objavljuje
+    fn post_types(&self) -> &[SipPostType];
+
+    // kljuc u bazi u prostoru imena izvora
+    fn storage_key(&self, name: &str) -> String {
+        return format!("{}_{}", self.id(), name);
+    }
+
+    // kljuc u bazi pod kojim se cuva poslednje stanje kolone
+    fn snapshot_key(&self, post_type: &SipPostType) -> String {
+        return self.storage_key(post_type.storage_suffix());
+    }
+
+    // ime autora u embedu
+    fn author_name(&self, post: &SipPost) -> String {
+        return format!("{} - {}", self.name(), post.post_type.display_name());
+    }
+
+    // kratak opis selektora za upozorenja o promeni izgleda stranice
+    fn selector_summary(&self) -> Option<String> {
+        return None;
+    }
+
+    // menja se kada se promeni nacin citanja stranice (npr. izmenjeni selektori)
+    // posle promene stranica se ponovo cita i ako se na izvoru nije promenila
+    fn parser_hash(&self) -> Option<String> {
+        return None;
+    }
+
+    // izvori koji ne podrzavaju uslovne zahteve ignorisu validators i uvek vracaju Modified
+    async fn fetch(&self, validators: &Validators) -> Result<FetchOutcome, SipError>;
+
+    // neispravni postovi se vracaju u ParsedPage::issues, greska znaci da stranica nije upotrebljiva
+    fn parse(&self, html: &str) -> Result<ParsedPage, SipError>;
+
+    // ceo tekst i prilozi posta sa stranice na koju vodi link
+    // izvori koji to ne podrzavaju vracaju None
+    async fn fetch_post_page(&self, _post: &SipPost) -> Result<Option<PostPage>, SipError> {
+        return Ok(None);
+    }
+}
+
+// najveca duzina html isecka u izvestaju o neispravnom postu
+const ISSUE_SNIPPET_LIMIT: usize = 300;
+
+// post koji nije mogao da se procita, ostali postovi sa stranice se i dalje salju
+#[derive(Debug, Clone)]
+pub struct ParseIssue {
+    pub post_type: SipPostType,
+    pub reason: String,
+    pub snippet: String
+}
+
+// rezultat parsiranja stranice, ispravni postovi i spisak preskocenih
+#[derive(Debug, Default)]
+pub struct ParsedPage {
+    pub posts: Vec<SipPost>,
+    pub issues: Vec<ParseIssue>
+}
+
+fn html_snippet(element: &ElementRef) -> String {
+    let html: String = element.html().split_whitespace().collect::<Vec<&str>>().join(" ");
+    if html.chars().count() <= ISSUE_SNIPPET_LIMIT {
+        return html;
+    }
+    let mut snippet: String = html.chars().take(ISSUE_SNIPPET_LIMIT - 1).collect();
+    snippet.push('…');
+    return snippet;
+}
+
+fn parse_element_to_post(post_element: ElementRef, post_type: SipPostType, selectors: &PostSelectors, base_url: Option<&Url>) -> Result<SipPost, SipError> {
+    let date: String = post_element
+        .select(&selectors.date)
+        .nth(selectors.date_nth)
+        .ok_or_else(|| SipError::PostParseError(format!("Error parsing date (selector `{}`, nth {})", selectors.raw.date, selectors.date_nth)))?
+        .text()
+        .map(|t| t.trim().to_string())
+        .collect();
+
+    let title: String = post_element
+        .select(&selectors.title)
+        .nth(selectors.title_nth)
+        .ok_or_else(|| SipError::PostParseError(format!("Error parsing title (selector `{}`, nth {})", selectors.raw.title, selectors.title_nth)))?
+        .text()
+        .map(|t| t.trim().to_string())
+        .collect();
+
+    let content_element: ElementRef = post_element
+        .select(&selectors.content)
+        .nth(selectors.content_nth)
+        .ok_or_else(|| SipError::PostParseError(format!("Error parsing content (selector `{}`, nth {})", selectors.raw.content, selectors.content_nth)))?;
+
+    let content: String = content_element
+        .text()
+        .map(|t| t.trim().to_string())
+        .collect();
+
+    let content_markdown: String = html_to_markdown(content_element, base_url);
+
+    let href: &str = post_element
+        .select(&selectors.link)
+        .nth(selectors.link_nth)
+        .ok_or_else(|| SipError::PostParseError(format!("Error parsing link (selector `{}`, nth {})", selectors.raw.link, selectors.link_nth)))?
+        .value()
+        .attr("href")
+        .ok_or_else(|| SipError::PostParseError(format!("Error parsing hyperlink (selector `{}` has no href)", selectors.raw.link)))?;
+    let link: String = resolve_link(base_url, href);
+
+    return Ok(SipPost {
+        post_type,
+        published_at: parse_sip_date(&date),
+        title,
+        content,
+        date,
+        key: post_key(&link),
+        link,
+        content_markdown: Some(content_markdown).filter(|markdown| !markdown.is_empty()),
+        full_content: None,
+        attachments: Vec::<SipAttachment>::new()
+    });
+}
+
+// neispravni postovi se preskacu i prijavljuju, umesto da obore celu stranicu
+// `base_url` je adresa stranice, za relativne linkove posta i sadrzaja
+pub fn parse_element_to_posts(node: ElementRef, post_type: SipPostType, selectors: &PostSelectors, base_url: Option<&Url>) -> ParsedPage {
+    let mut page: ParsedPage = ParsedPage::default();
+
+    for post_element in node.select(&selectors.posts) {
+        match parse_element_to_post(post_element, post_type, selectors, base_url) {
+            Ok(post) => page.posts.push(post),
+            Err(why) => page.issues.push(ParseIssue {
+                post_type,
+                reason: why.to_string(),
+                snippet: html_snippet(&post_element)
+            })
+        };
+    }
+
+    return page;
+}
+
+// ekstenzije fajlova koji se tretiraju kao prilozi
+const ATTACHMENT_EXTENSIONS: [&str; 9] = ["pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "zip", "rar"];
+
+fn attachment_from_link(page_url: &Url, href: &str, text: &str) -> Option<SipAttachment> {
+    let url: Url = page_url.join(href.trim()).ok()?;
+    let file_name: String = url.path_segments()?.next_back()?.to_string();
+    let extension: String = file_name.rsplit_once('.')?.1.to_lowercase();
+
+    if !ATTACHMENT_EXTENSIONS.contains(&extension.as_str()) {
+        return None;
+    }
+
+    // tekst linka je citljiviji, ali samo ako sadrzi ekstenziju
+    let text: &str = text.trim();
+    let name: String = if text.to_lowercase().ends_with(&format!(".{}", extension)) {
+        text.to_string()
+    } else {
+        file_name
+    };
+
+    return Some(SipAttachment {
+        name,
+        url: url.to_string(),
+        size: None
+    });
+}
+
+// tekst clanka kao discord markdown i linkovi ka fajlovima
+pub fn parse_post_page(html: &str, page_link: &str) -> PostPage {
+    let selectors: Arc<PostSelectors> = selectors::current();
+    let document: Html = Html::parse_document(html);
+
+    let content: Option<String> = document
+        .select(&selectors.article)
+        .nth(0)
+        .map(|article| html_to_markdown(article, Url::parse(page_link).ok().as_ref()))
+        .filter(|text| !text.is_empty());
+
+    let mut attachments: Vec<SipAttachment> = Vec::<SipAttachment>::new();
+    if let Ok(page_url) = Url::parse(page_link) {
+        for link in document.select(&selectors.file_link) {
+            let href: &str = link.value().attr("href").unwrap_or_default();
+            let text: String = link.text().collect();
+            if let Some(attachment) = attachment_from_link(&page_url, href, &text) {
+                if !attachments.iter().any(|existing| existing.url == attachment.url) {
+                    attachments.push(attachment);
+                }
+            }
+        }
+    }
+
+    return PostPage { content, attachments };
+}
+
+// velicina fajla iz Content-Length zaglavlja
+async fn fetch_attachment_size(url: &str) -> Option<u64> {
+    let response: Response = http_client::client().head(url).send().await.ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    return response.content_length();
+}
+
+// postovi iz svih kolona stranice, nedostajuca kolona znaci da stranica nije upotrebljiva
+fn parse_columns(html: &str, selectors: &PostSelectors, base_url: Option<&Url>) -> Result<ParsedPage, SipError> {
+    let document: Html = Html::parse_document(html);
+
+    let columns: Vec<ElementRef> = document.select(&selectors.columns).collect();
+    if columns.len() < selectors.column_types.len() {
+        return Err(SipError::PostError(format!("Missing posts: selector `{}` expected {} columns, found {}", selectors.raw.columns, selectors.column_types.len(), columns.len())));
+    }
+
+    let mut page: ParsedPage = ParsedPage::default();
+    for (column, post_type) in columns.into_iter().zip(selectors.column_types.iter()) {
+        let column_page: ParsedPage = parse_element_to_posts(column, *post_type, selectors, base_url);
+        page.posts.extend(column_page.posts);
+        page.issues.extend(column_page.issues);
+    }
+
+    return Ok(page);
+}
+
+// sip.elfak.ni.ac.rs
+// levo - najnovije vesti, desno - vazna obavestenja
+pub struct SipSource;
+
+#[serenity::async_trait]
+impl PostSource for SipSource {
+    fn id(&self) -> &str {
+        return "sip";
+    }
+
+    fn name(&self) -> &str {
+        return "SIP";
+    }
+
+    fn url(&self) -> &str {
+        return "https://sip.elfak.ni.ac.rs/";
+    }
+
+    fn post_types(&self) -> &[SipPostType] {
+        return &[SipPostType::New, SipPostType::Important];
+    }
+
+    // zadrzavamo stare kljuceve da ne bi izgubili postojece stanje
+    fn snapshot_key(&self, post_type: &SipPostType) -> String {
+        return match post_type {
+            SipPostType::New => "levi_stari".to_string(),
+            SipPostType::Important => "desni_stari".to_string()
+        };
+    }
+
+    fn author_name(&self, post: &SipPost) -> String {
+        return post.post_type.display_name().to_string();
+    }
+
+    fn selector_summary(&self) -> Option<String> {
+        let selectors: Arc<PostSelectors> = selectors::current();
+        return Some(format!("columns `{}`, posts `{}`", selectors.raw.columns, selectors.raw.posts));
+    }
+
+    fn parser_hash(&self) -> Option<String> {
+        return Some(selectors::current().hash.clone());
+    }
+
+    async fn fetch(&self, validators: &Validators) -> Result<FetchOutcome, SipError> {
+        return http_client::get_text_conditional(self.url(), validators).await;
+    }
+
+    fn parse(&self, html: &str) -> Result<ParsedPage, SipError> {
+        return parse_columns(html, &selectors::current(), Url::parse(self.url()).ok().as_ref());
+    }
+
+    async fn fetch_post_page(&self, post: &SipPost) -> Result<Option<PostPage>, SipError> {
+        let html: String = http_client::get_text(&post.link).await?;
+
+        let mut page: PostPage = parse_post_page(&html, &post.link);
+        for attachment in page.attachments.iter_mut() {
+            attachment.size = fetch_attachment_size(&attachment.url).await;
+        }
+
+        return Ok(Some(page));
+    }
+}
+
+// svi izvori koje fetcher obilazi
+// novi izvor se dodaje implementacijom PostSource i upisom ovde
+pub fn all_sources() -> Vec<Box<dyn PostSource>> {
+    return vec![
+        Box::new(SipSource)
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selectors::SelectorConfig;
+
+    // naslovna strana sa po jednim neispravnim postom u svakoj koloni
+    const FRONT_PAGE: &str = r#"
+        <html><body>
+            <div class="news-box"><ul>
+                <li><a href="/vesti/1"><h4>Prvi</h4></a><p>01.10.2024.</p><p>Sadrzaj <b>prvog</b> posta</p></li>
+                <li><a href="/vesti/2"></a><p>02.10.2024.</p><p>Post bez naslova</p></li>
+                <li><h4>Bez linka</h4><p>03.10.2024.</p><p>Post bez linka</p></li>
+                <li><a href="vesti/4"><h4>Cetvrti</h4></a><p>04.10.2024.</p><p>Relativan link</p></li>
+            </ul></div>
+            <div class="news-box"><ul>
+                <li><a href="/vesti/5"><h4>Bez datuma</h4></a></li>
+                <li><a href="https://sip.elfak.ni.ac.rs/vesti/6"><h4>Vazno</h4></a><p>05.10.2024.</p><p>Vazno obavestenje</p></li>
+            </ul></div>
+        </body></html>
+    "#;
+
+    fn default_selectors() -> PostSelectors {
+        return PostSelectors::from_config(&SelectorConfig::default()).unwrap();
+    }
+
+    fn parse(html: &str) -> Result<ParsedPage, SipError> {
+        return parse_columns(html, &default_selectors(), Url::parse(SipSource.url()).ok().as_ref());
+    }
+
+    #[test]
+    fn broken_posts_are_skipped() {
+        let page: ParsedPage = parse(FRONT_PAGE).unwrap();
+
+        let posts: Vec<(SipPostType, &str, &str)> = page.posts.iter().map(|post| (post.post_type, post.title.as_str(), post.link.as_str())).collect();
+        assert_eq!(posts, vec![
+            (SipPostType::New, "Prvi", "https://sip.elfak.ni.ac.rs/vesti/1"),
+            (SipPostType::New, "Cetvrti", "https://sip.elfak.ni.ac.rs/vesti/4"),
+            (SipPostType::Important, "Vazno", "https://sip.elfak.ni.ac.rs/vesti/6")
+        ]);
+        assert_eq!(page.posts[0].date, "01.10.2024.");
+        assert_eq!(page.posts[0].content_markdown.as_deref(), Some("Sadrzaj **prvog** posta"));
+
+        // fetcher stranicu sa preskocenim postovima smatra delimicnom
+        let issues: Vec<(SipPostType, &str)> = page.issues.iter().map(|issue| (issue.post_type, issue.reason.as_str())).collect();
+        assert_eq!(issues.len(), 3);
+        assert_eq!(issues[0].0, SipPostType::New);
+        assert!(issues[0].1.contains("Error parsing title"), "{}", issues[0].1);
+        assert!(issues[1].1.contains("Error parsing link"), "{}", issues[1].1);
+        assert_eq!(issues[2].0, SipPostType::Important);
+        assert!(issues[2].1.contains("Error parsing date"), "{}", issues[2].1);
+        assert!(page.issues[1].snippet.contains("Bez linka"));
+    }
+
+    #[test]
+    fn missing_column_fails_the_page() {
+        let html: &str = r#"<div class="news-box"><ul><li><a href="/vesti/1"><h4>Prvi</h4></a><p>01.10.2024.</p><p>Sadrzaj</p></li></ul></div>"#;
+
+        let error: String = parse(html).err().unwrap().to_string();
+
+        assert!(error.contains("expected 2 columns, found 1"), "{}", error);
+    }
+
+    #[test]
+    fn long_snippet_is_shortened() {
+        let html: String = format!(r#"<div class="news-box"><ul><li><p>{}</p></li></ul></div><div class="news-box"></div>"#, "a".repeat(1000));
+
+        let page: ParsedPage = parse(&html).unwrap();
+
+        assert!(page.posts.is_empty());
+        assert_eq!(page.issues[0].snippet.chars().count(), ISSUE_SNIPPET_LIMIT);
+        assert!(page.issues[0].snippet.ends_with('…'));
+    }
+}
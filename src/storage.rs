@@ -1,35 +1,138 @@
+use std::collections::HashMap;
 use std::fs;
 
-use pickledb::PickleDb;
-use serenity::model::webhook::Webhook;
+use reqwest::Url;
 
-use crate::{errors::SipError, fetcher::SipPost};
+use crate::config::CONFIG;
+use crate::database::{BackupOptions, Database};
+use crate::delivery::{Delivery, OutboxEntry};
+use crate::archive::ArchivedPost;
+use crate::links::{post_key, resolve_link, stored_post_key};
+use crate::logger;
+use crate::repository::Repository;
+use crate::{errors::SipError, fetcher::SipPost, sources::PostSource};
+
+// sqlite baza, zajedno sa prebacivanjem stare baze
+pub fn open_repository(sources: &[Box<dyn PostSource>]) -> Result<Box<dyn Repository>, SipError> {
+    let mut database: Database = Database::open(&CONFIG.database_file, BackupOptions::from_config())?;
+    // stara storage.json baza, samo pri prvom pokretanju posle prelaska na sqlite
+    if database.import_legacy_storage(&CONFIG.legacy_storage_file, sources)? {
+        logger::log_sync("DB", format!("Imported {} into {}", CONFIG.legacy_storage_file, CONFIG.database_file));
+    }
+    return Ok(Box::new(database));
+}
 
 // u isto vreme i vrsi validaciju
-pub fn setup_storage(database: &mut PickleDb) -> Result<(), SipError> {
+pub fn setup_storage(database: &mut dyn Repository, sources: &[Box<dyn PostSource>]) -> Result<(), SipError> {
     // logs
     fs::create_dir_all("./logs").map_err(|err| SipError::FileSystemError(err.to_string()))?;
 
-    // hook
-    if !database.exists("sip_hooks") {
-        database.set("sip_hooks", &Vec::<Webhook>::new()).map_err(|err| SipError::StorageError(err.to_string()))?;
+    database.atomically(|database| normalize_post_keys(database, sources))?;
+
+    return Ok(());
+}
+
+// stare verzije su postove prepoznavale po linku kako je napisan na stranici, a zatim po "host:id"
+// kljucevi u stanju, arhivi i porukama se ponovo racunaju iz linkova postova (SipPost::key)
+// ponovno pokretanje ne menja nista
+fn normalize_post_keys(database: &mut dyn Repository, sources: &[Box<dyn PostSource>]) -> Result<(), SipError> {
+    // stari kljuc -> novi kljuc, za vrednosti koje ne cuvaju link posta
+    let mut renamed: HashMap<String, String> = HashMap::<String, String>::new();
+
+    for source in sources {
+        let base_url: Option<Url> = Url::parse(source.url()).ok();
+
+        for post_type in source.post_types() {
+            let snapshot_key: String = source.snapshot_key(post_type);
+            let mut posts: Vec<SipPost> = database.snapshot(&snapshot_key)?;
+            let mut changed: bool = false;
+            for post in posts.iter_mut() {
+                let link: String = resolve_link(base_url.as_ref(), &post.link);
+                let key: String = post_key(&link);
+                if link != post.link || key != post.key {
+                    if !post.key.is_empty() {
+                        renamed.insert(post.key.clone(), key.clone());
+                    }
+                    post.link = link;
+                    post.key = key;
+                    changed = true;
+                }
+            }
+            if changed {
+                database.set_snapshot(&snapshot_key, &posts)?;
+            }
+        }
     }
 
-    // levi - najnovije vesti
-    if !database.exists("levi_stari") {
-        database.set("levi_stari", &Vec::<SipPost>::new()).map_err(|err| SipError::StorageError(err.to_string()))?;
+    let archive: HashMap<String, ArchivedPost> = database.archived_posts()?;
+    let mut rekeyed: HashMap<String, ArchivedPost> = HashMap::<String, ArchivedPost>::new();
+    for (stored, mut archived) in archive {
+        let key: String = post_key(&archived.post.link);
+        if key != stored {
+            database.delete_archived_post(&stored)?;
+            renamed.insert(stored, key.clone());
+            archived.post.key = key.clone();
+            rekeyed.insert(key, archived);
+        }
     }
-    // if !database.exists("levi_novi") {
-    //     database.set("levi_novi", &Vec::<SipPost>::new()).map_err(|err| SipError::StorageError(err.to_string()))?;
-    // }
+    if !rekeyed.is_empty() {
+        database.save_archived_posts(&rekeyed)?;
+    }
+
+    for source in sources {
+        let base_url: Option<Url> = Url::parse(source.url()).ok();
+        let mut stale: Vec<String> = Vec::<String>::new();
+        let mut normalized: HashMap<String, String> = HashMap::<String, String>::new();
+        for (stored, hash) in database.post_hashes(source.as_ref())? {
+            let key: String = rekey(&renamed, base_url.as_ref(), &stored);
+            if key != stored {
+                stale.push(stored);
+                normalized.insert(key, hash);
+            }
+        }
+        if !stale.is_empty() {
+            database.delete_post_hashes(source.as_ref(), &stale)?;
+            database.save_post_hashes(source.as_ref(), &normalized)?;
+        }
+    }
+
+    // poruke i outbox ne pamte izvor
+    // stare verzije su citale samo sip, pa se relativni linkovi razresavaju u odnosu na prvi izvor
+    let base_url: Option<Url> = sources.first().and_then(|source| Url::parse(source.url()).ok());
 
-    // desni - vazna obavestenja
-    if !database.exists("desni_stari") {
-        database.set("desni_stari", &Vec::<SipPost>::new()).map_err(|err| SipError::StorageError(err.to_string()))?;
+    for stored in database.delivered_post_keys()? {
+        let key: String = rekey(&renamed, base_url.as_ref(), &stored);
+        if key != stored {
+            database.rename_delivered_post(&stored, &key)?;
+        }
+    }
+
+    let mut outbox: Vec<OutboxEntry> = database.outbox()?;
+    for entry in outbox.iter_mut() {
+        let mut changed: bool = false;
+        let keys: Vec<&mut String> = match &mut entry.delivery {
+            Delivery::Send(message) => message.links.iter_mut().flatten().collect(),
+            Delivery::Edit { link, .. } => vec![link]
+        };
+        for stored in keys {
+            let key: String = rekey(&renamed, base_url.as_ref(), stored);
+            if key != *stored {
+                *stored = key;
+                changed = true;
+            }
+        }
+        if changed {
+            database.update_delivery(entry.id, &entry.delivery)?;
+        }
     }
-    // if !database.exists("desni_novi") {
-    //     database.set("desni_novi", &Vec::<SipPost>::new()).map_err(|err| SipError::StorageError(err.to_string()))?;
-    // }
 
     return Ok(());
 }
+
+// novi kljuc za sacuvanu vrednost, stari kljucevi bez putanje se ne mogu izracunati bez linka
+fn rekey(renamed: &HashMap<String, String>, base_url: Option<&Url>, stored: &str) -> String {
+    return match renamed.get(stored) {
+        Some(key) => key.clone(),
+        None => stored_post_key(base_url, stored)
+    };
+}
@@ -0,0 +1,44 @@
+use serde_json::json;
+use serenity::model::webhook::Webhook;
+
+use crate::fetcher::{SipAttachment, SipPost, SipPostType};
+use crate::links::post_key;
+use crate::repository::{MemoryRepository, Repository};
+
+// zajednicki podaci za testove
+
+// SipBot webhook sa tokenom, kanal je id + 100
+pub fn webhook(id: u64, guild_id: u64) -> Webhook {
+    return serde_json::from_value(json!({
+        "id": id.to_string(),
+        "type": 1,
+        "channel_id": (id + 100).to_string(),
+        "guild_id": guild_id.to_string(),
+        "name": "SipBot Webhook",
+        "token": "token"
+    })).unwrap();
+}
+
+// post sa sip-a sa linkom /vesti/<id>
+pub fn post(post_type: SipPostType, id: u32, title: &str) -> SipPost {
+    let link: String = format!("https://sip.elfak.ni.ac.rs/vesti/{}", id);
+    return SipPost {
+        post_type,
+        date: "01.10.2023.".to_string(),
+        published_at: None,
+        title: title.to_string(),
+        content: format!("Sadrzaj posta {}", id),
+        key: post_key(&link),
+        link,
+        content_markdown: None,
+        full_content: None,
+        attachments: Vec::<SipAttachment>::new()
+    };
+}
+
+// prazno stanje sa jednim webhook-om (id 1)
+pub fn repository() -> MemoryRepository {
+    let mut repository: MemoryRepository = MemoryRepository::default();
+    repository.save_webhook(&webhook(1, 3)).unwrap();
+    return repository;
+}
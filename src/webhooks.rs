@@ -0,0 +1,323 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use crate::repository::{Repository, SharedRepository};
+use serde::{Serialize, Deserialize};
+use serenity::http::{Http, HttpError};
+use serenity::futures::TryFutureExt;
+use serenity::model::prelude::{GuildId, UserId};
+use serenity::model::webhook::Webhook;
+
+use crate::config::CONFIG;
+use crate::errors::{DiscordError, FetchErrorKind, SipError};
+use crate::logger;
+
+pub const WEBHOOK_NAME: &str = "SipBot Webhook";
+
+// webhook-ovi uklonjeni iz baze jer vise ne postoje na discord-u
+pub const PRUNED_WEBHOOKS_KEY: &str = "sip_pruned_hooks";
+
+// discord json kodovi gresaka
+const UNKNOWN_CHANNEL: isize = 10003;
+const UNKNOWN_MESSAGE: isize = 10008;
+const UNKNOWN_WEBHOOK: isize = 10015;
+const MISSING_ACCESS: isize = 50001;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PrunedWebhook {
+    pub webhook_id: u64,
+    pub channel_id: u64,
+    pub guild_id: Option<u64>,
+    pub reason: String,
+    // unix vreme uklanjanja
+    pub pruned_at: i64
+}
+
+pub fn is_sipbot_webhook(webhook: &Webhook, bot_id: UserId) -> bool {
+    return match (&webhook.name, &webhook.user) {
+        (Some(name), Some(user)) => name == WEBHOOK_NAME && user.id == bot_id,
+        _ => false
+    };
+}
+
+// razlog zbog kog webhook vise ne moze da se koristi
+// prolazne greske (mreza, 5xx, rate limit) vracaju None
+pub fn dead_webhook_reason(error: &serenity::Error) -> Option<String> {
+    if let serenity::Error::Http(http_error) = error {
+        if let HttpError::UnsuccessfulRequest(response) = http_error.as_ref() {
+            return match response.error.code {
+                UNKNOWN_WEBHOOK => Some("Unknown Webhook".to_string()),
+                UNKNOWN_CHANNEL => Some("Unknown Channel".to_string()),
+                MISSING_ACCESS => Some("Missing Access".to_string()),
+                // nepostojeca poruka nije razlog da se webhook ukloni
+                UNKNOWN_MESSAGE => None,
+                _ if response.status_code.as_u16() == 404 => Some(format!("Not Found: {}", response.error.message)),
+                _ => None
+            };
+        }
+    }
+    return None;
+}
+
+// greska zbog same poruke (npr. 400 Invalid Form Body), ponovno slanje ne pomaze
+// 429 i greske servera su prolazne
+pub fn is_permanent_error(error: &serenity::Error) -> bool {
+    if let serenity::Error::Http(http_error) = error {
+        if let HttpError::UnsuccessfulRequest(response) = http_error.as_ref() {
+            let status: u16 = response.status_code.as_u16();
+            return (400..500).contains(&status) && !FetchErrorKind::Status(status).is_retryable();
+        }
+    }
+    return false;
+}
+
+pub fn is_unknown_message(error: &serenity::Error) -> bool {
+    if let serenity::Error::Http(http_error) = error {
+        if let HttpError::UnsuccessfulRequest(response) = http_error.as_ref() {
+            return response.error.code == UNKNOWN_MESSAGE;
+        }
+    }
+    return false;
+}
+
+// brise webhook iz baze zajedno sa porukama koje ga cekaju, poslatim porukama i ulogom
+pub fn remove_webhook(database: &mut dyn Repository, webhook_id: u64) -> Result<(), SipError> {
+    database.delete_webhook(webhook_id)?;
+    database.delete_webhook_deliveries(webhook_id)?;
+    database.delete_webhook_messages(webhook_id)?;
+    database.set_ping_role(webhook_id, None)?;
+    return Ok(());
+}
+
+// uklanja webhook koji vise ne postoji na discord-u i pamti razlog
+pub fn prune_webhook(database: &mut dyn Repository, webhook_id: u64, reason: &str) -> Result<Option<PrunedWebhook>, SipError> {
+    let webhook: Webhook = match database.webhooks()?.into_iter().find(|webhook| webhook.id.0 == webhook_id) {
+        Some(webhook) => webhook,
+        None => return Ok(None)
+    };
+    remove_webhook(database, webhook_id)?;
+
+    let pruned: PrunedWebhook = PrunedWebhook {
+        webhook_id,
+        channel_id: webhook.channel_id.map(|channel_id| channel_id.0).unwrap_or_default(),
+        guild_id: webhook.guild_id.map(|guild_id| guild_id.0),
+        reason: reason.to_string(),
+        pruned_at: Utc::now().timestamp()
+    };
+
+    database.add_pruned_webhook(&pruned)?;
+
+    return Ok(Some(pruned));
+}
+
+// obavestava vlasnika bota porukom u privatnom kanalu, ako je to podeseno
+pub async fn notify_owner_about_pruned(http: &Http, owner_id: UserId, pruned: &PrunedWebhook) -> Result<(), DiscordError> {
+    logger::log("HOOKS", format!("Webhook {} in channel {} removed: {}", pruned.webhook_id, pruned.channel_id, pruned.reason)).await;
+
+    if !CONFIG.notify_owner_on_prune {
+        return Ok(());
+    }
+
+    let dm_channel = owner_id.create_dm_channel(http).map_err(|err| DiscordError::DiscordMessageError(err.to_string())).await?;
+    dm_channel.send_message(http, |m|
+        m
+            .embed(|e|
+                e
+                    .author(|a| a.name("SIP").url("https://sip.elfak.ni.ac.rs/"))
+                    .thumbnail("https://i.imgur.com/dyu12dZ.png")
+                    .title(":warning: WebHook uklonjen :warning:")
+                    .description(format!("Webhook u kanalu <#{}> vise nije dostupan i uklonjen je iz baze.\nRazlog: {}", pruned.channel_id, pruned.reason))
+                    .color(0x65BD36)
+            )
+    ).map_err(|err| DiscordError::DiscordMessageError(err.to_string())).await?;
+
+    return Ok(());
+}
+
+#[derive(Debug, Default)]
+pub struct ReconcileReport {
+    pub refreshed: usize,
+    pub discovered: usize,
+    pub pruned: Vec<PrunedWebhook>
+}
+
+// izmene koje treba upisati u bazu posle poredjenja sa discord-om
+#[derive(Debug, Default)]
+struct WebhookChanges {
+    refresh: Vec<Webhook>,
+    discover: Vec<Webhook>,
+    // id webhook-a i razlog uklanjanja
+    prune: Vec<(u64, String)>
+}
+
+// `guild_webhooks` sadrzi samo servere ciji je spisak procitan, ostali serveri se ne diraju
+fn diff_webhooks(stored_webhooks: &[Webhook], guild_webhooks: &HashMap<u64, Vec<Webhook>>, guild_ids: &[GuildId], complete: bool) -> WebhookChanges {
+    let known_guilds: HashSet<u64> = guild_ids.iter().map(|guild_id| guild_id.0).collect();
+    let mut changes: WebhookChanges = WebhookChanges::default();
+
+    for stored in stored_webhooks.iter() {
+        let guild_id: u64 = stored.guild_id.map(|guild_id| guild_id.0).unwrap_or_default();
+        match guild_webhooks.get(&guild_id) {
+            Some(webhooks) => match webhooks.iter().find(|webhook| webhook.id == stored.id) {
+                Some(fresh) => {
+                    let mut refreshed: Webhook = fresh.clone();
+                    refreshed.token = fresh.token.clone().or_else(|| stored.token.clone());
+                    changes.refresh.push(refreshed);
+                },
+                None => changes.prune.push((stored.id.0, "Webhook no longer exists".to_string()))
+            },
+            None if complete && !known_guilds.contains(&guild_id) => changes.prune.push((stored.id.0, "Bot is no longer in the guild".to_string())),
+            None => {}
+        };
+    }
+
+    for webhook in guild_webhooks.values().flatten() {
+        if webhook.token.is_some() && !stored_webhooks.iter().any(|stored| stored.id == webhook.id) {
+            changes.discover.push(webhook.clone());
+        }
+    }
+
+    return changes;
+}
+
+// poredi webhook-ove u bazi sa webhook-ovima na discord-u za zadate servere
+// nedostajuci SipBot webhook-ovi se dodaju, postojeci osvezavaju, a obrisani uklanjaju
+// `complete` oznacava da je spisak servera potpun, pa se uklanjaju i webhook-ovi sa servera na kojima bot vise nije
+pub async fn reconcile_webhooks(http: &Http, repository: &SharedRepository, bot_id: UserId, guild_ids: &[GuildId], complete: bool) -> Result<ReconcileReport, SipError> {
+    let mut guild_webhooks: HashMap<u64, Vec<Webhook>> = HashMap::<u64, Vec<Webhook>>::new();
+
+    for guild_id in guild_ids {
+        match guild_id.webhooks(http).await {
+            Ok(webhooks) => {
+                guild_webhooks.insert(guild_id.0, webhooks.into_iter().filter(|webhook| is_sipbot_webhook(webhook, bot_id)).collect());
+            },
+            // bez pristupa spisku, webhook-ovi tog servera ostaju kakvi jesu
+            Err(why) => logger::log("HOOKS", format!("Can't list webhooks of guild {}: {}", guild_id.0, why)).await
+        };
+    }
+
+    let mut database = repository.lock().await;
+    let changes: WebhookChanges = diff_webhooks(&database.webhooks()?, &guild_webhooks, guild_ids, complete);
+    let mut report: ReconcileReport = ReconcileReport {
+        refreshed: changes.refresh.len(),
+        discovered: changes.discover.len(),
+        ..ReconcileReport::default()
+    };
+
+    for webhook in changes.refresh.iter().chain(changes.discover.iter()) {
+        database.save_webhook(webhook)?;
+    }
+
+    for (webhook_id, reason) in changes.prune {
+        if let Some(pruned) = database.atomically(|database| prune_webhook(database, webhook_id, &reason))? {
+            report.pruned.push(pruned);
+        }
+    }
+
+    return Ok(report);
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{StatusCode, Url};
+    use serenity::http::error::{DiscordJsonError, ErrorResponse};
+    use serde_json::json;
+
+    use super::*;
+    use crate::delivery::DeliveredMessage;
+    use crate::repository::MemoryRepository;
+    use crate::test_fixtures::{repository, webhook};
+
+    fn http_error(status: u16, code: isize) -> serenity::Error {
+        let error: DiscordJsonError = serde_json::from_value(json!({"code": code, "message": "Greska"})).unwrap();
+        return serenity::Error::Http(Box::new(HttpError::UnsuccessfulRequest(ErrorResponse {
+            status_code: StatusCode::from_u16(status).unwrap(),
+            url: Url::parse("https://discord.com/api/v10/webhooks/1/token").unwrap(),
+            error
+        })));
+    }
+
+    fn without_token(mut webhook: Webhook) -> Webhook {
+        webhook.token = None;
+        return webhook;
+    }
+
+    fn ids(webhooks: &[Webhook]) -> Vec<u64> {
+        let mut ids: Vec<u64> = webhooks.iter().map(|webhook| webhook.id.0).collect();
+        ids.sort();
+        return ids;
+    }
+
+    #[test]
+    fn error_codes() {
+        assert!(dead_webhook_reason(&http_error(404, UNKNOWN_WEBHOOK)).is_some());
+        assert!(dead_webhook_reason(&http_error(404, UNKNOWN_CHANNEL)).is_some());
+        assert!(dead_webhook_reason(&http_error(403, MISSING_ACCESS)).is_some());
+        assert!(dead_webhook_reason(&http_error(404, 0)).is_some());
+        assert!(dead_webhook_reason(&http_error(404, UNKNOWN_MESSAGE)).is_none());
+        assert!(dead_webhook_reason(&http_error(500, 0)).is_none());
+        assert!(dead_webhook_reason(&http_error(429, 0)).is_none());
+
+        assert!(is_unknown_message(&http_error(404, UNKNOWN_MESSAGE)));
+        assert!(!is_unknown_message(&http_error(404, UNKNOWN_WEBHOOK)));
+
+        assert!(is_permanent_error(&http_error(400, 50035)));
+        assert!(!is_permanent_error(&http_error(429, 0)));
+        assert!(!is_permanent_error(&http_error(502, 0)));
+    }
+
+    #[test]
+    fn listed_guild_is_reconciled() {
+        let stored: Vec<Webhook> = vec![webhook(1, 3), webhook(2, 3)];
+        // webhook 1 je i dalje tu bez tokena, webhook 2 je obrisan, webhook 4 je nov
+        let listed: HashMap<u64, Vec<Webhook>> = HashMap::from([(3, vec![without_token(webhook(1, 3)), webhook(4, 3), without_token(webhook(5, 3))])]);
+
+        let changes: WebhookChanges = diff_webhooks(&stored, &listed, &[GuildId(3)], true);
+
+        assert_eq!(ids(&changes.refresh), vec![1]);
+        assert_eq!(changes.refresh[0].token.as_deref(), Some("token"));
+        assert_eq!(ids(&changes.discover), vec![4]);
+        assert_eq!(changes.prune, vec![(2, "Webhook no longer exists".to_string())]);
+    }
+
+    #[test]
+    fn unlisted_guild_keeps_its_webhooks() {
+        let stored: Vec<Webhook> = vec![webhook(1, 3), webhook(2, 6)];
+        let listed: HashMap<u64, Vec<Webhook>> = HashMap::from([(3, vec![webhook(1, 3)])]);
+
+        // spisak servera nije potpun, server 6 mozda i dalje postoji
+        let changes: WebhookChanges = diff_webhooks(&stored, &listed, &[GuildId(3)], false);
+        assert_eq!(ids(&changes.refresh), vec![1]);
+        assert!(changes.prune.is_empty());
+
+        // bot vise nije na serveru 6
+        let changes: WebhookChanges = diff_webhooks(&stored, &listed, &[GuildId(3)], true);
+        assert_eq!(changes.prune, vec![(2, "Bot is no longer in the guild".to_string())]);
+    }
+
+    #[test]
+    fn failed_listing_keeps_webhooks() {
+        let stored: Vec<Webhook> = vec![webhook(1, 3)];
+
+        // server 3 je poznat, ali njegov spisak nije procitan
+        let changes: WebhookChanges = diff_webhooks(&stored, &HashMap::<u64, Vec<Webhook>>::new(), &[GuildId(3)], true);
+
+        assert!(changes.refresh.is_empty());
+        assert!(changes.prune.is_empty());
+    }
+
+    #[test]
+    fn pruned_webhook_is_removed_with_its_data() {
+        let mut repository: MemoryRepository = repository();
+        repository.set_ping_role(1, Some(7)).unwrap();
+        repository.add_delivered_messages(&[DeliveredMessage { webhook_id: 1, message_id: 50, embed_index: 0, link: "post".to_string() }]).unwrap();
+
+        let pruned: PrunedWebhook = prune_webhook(&mut repository, 1, "Unknown Webhook").unwrap().unwrap();
+
+        assert_eq!((pruned.channel_id, pruned.guild_id), (101, Some(3)));
+        assert!(repository.webhooks().unwrap().is_empty());
+        assert!(repository.ping_roles().unwrap().is_empty());
+        assert!(repository.delivered_messages(1, "post").unwrap().is_empty());
+        assert!(prune_webhook(&mut repository, 1, "Unknown Webhook").unwrap().is_none());
+    }
+}